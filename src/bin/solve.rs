@@ -0,0 +1,82 @@
+use std::{env, fs, process};
+
+use bevy_ecs_ldtk::ldtk::LdtkJson;
+use bevy_jam_3::{
+    level::build_meta_levels,
+    puzzle::PuzzleState,
+    solver::{solve, SolveOutcome},
+    tiles::TileDefinitions,
+    validation::validate_levels,
+};
+
+const LDTK_PATH: &str = "assets/levels/beside_yourself.ldtk";
+const TILES_PATH: &str = "assets/levels/beside_yourself.tiles.ron";
+const MAX_STATES: usize = 2_000_000;
+
+// Prints the optimal number of actions and one optimal solution for every
+// meta-level in the LDtk project. With `--check`, prints any problems with the
+// level set instead and exits with an error if there are any.
+//
+//     cargo run --bin solve -- [--check] [path/to/project.ldtk]
+fn main() {
+    let mut check = false;
+    let mut path = LDTK_PATH.to_owned();
+    for arg in env::args().skip(1) {
+        if arg == "--check" {
+            check = true;
+        } else {
+            path = arg;
+        }
+    }
+    let contents = fs::read_to_string(&path).unwrap_or_else(|err| {
+        eprintln!("could not read {path}: {err}");
+        process::exit(1);
+    });
+    let project: LdtkJson = serde_json::from_str(&contents).unwrap_or_else(|err| {
+        eprintln!("could not parse {path}: {err}");
+        process::exit(1);
+    });
+    let tile_definitions = fs::read_to_string(TILES_PATH)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| TileDefinitions::from_ron(&contents).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            eprintln!("could not load {TILES_PATH}: {err}");
+            process::exit(1);
+        });
+    let levels = project
+        .levels
+        .iter()
+        .chain(project.worlds.iter().flat_map(|world| world.levels.iter()))
+        .collect::<Vec<_>>();
+
+    if check {
+        let issues = validate_levels(levels.iter().copied(), &tile_definitions, MAX_STATES);
+        for issue in &issues {
+            println!("{issue}");
+        }
+        if !issues.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+
+    for meta_level in build_meta_levels(levels.iter().copied()) {
+        let puzzle =
+            PuzzleState::from_ldtk_levels(&meta_level, levels.iter().copied(), &tile_definitions);
+        let level_name = format!("Level {}", meta_level.level_num + 1);
+        match solve(&puzzle, MAX_STATES) {
+            SolveOutcome::Solved(actions) => {
+                let actions = actions
+                    .iter()
+                    .map(|action| action.to_string())
+                    .collect::<Vec<_>>();
+                println!("{level_name}: {} actions", actions.len());
+                println!("    {}", actions.join(", "));
+            }
+            SolveOutcome::Unsolvable => println!("{level_name}: unsolvable"),
+            SolveOutcome::SearchLimitReached => {
+                println!("{level_name}: gave up after {MAX_STATES} states")
+            }
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use crate::{
-    level::{CurrentMetaLevel, LevelPosition},
-    player::PrimaryPlayer,
+    level::{
+        CurrentMetaLevel, CurrentPuzzle, LevelPosition, MetaGridCoords, MetaLevel, SubLevelIndex,
+    },
+    player::{Direction, PrimaryPlayer},
+    puzzle::Orientation,
     util::grid_coords_to_tile_pos,
     GameState,
 };
@@ -20,15 +23,21 @@ impl Plugin for BoundaryPlugin {
         .add_systems(
             (
                 clear_boundary_arrows,
-                update_boundary_arrows_pointing_from.run_if(any_with_component::<PrimaryPlayer>()),
-                update_boundary_arrows_pointing_to.run_if(any_with_component::<PrimaryPlayer>()),
+                update_boundary_arrows_pointing_from.run_if(
+                    resource_exists::<CurrentPuzzle>()
+                        .and_then(any_with_component::<PrimaryPlayer>()),
+                ),
+                update_boundary_arrows_pointing_to.run_if(
+                    resource_exists::<CurrentPuzzle>()
+                        .and_then(any_with_component::<PrimaryPlayer>()),
+                ),
             )
                 .in_set(OnUpdate(GameState::InGame)),
         );
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BoundaryEdge {
     Top,
     Bottom,
@@ -36,12 +45,67 @@ pub enum BoundaryEdge {
     Right,
 }
 
+impl BoundaryEdge {
+    // the edge of a turned sub-level's own tiles that ends up on this edge on
+    // screen. the arrow tiles turn along with them, so they still point the
+    // right way.
+    fn local(self, orientation: Orientation) -> Self {
+        let direction = match self {
+            Self::Top => Direction::Up,
+            Self::Bottom => Direction::Down,
+            Self::Left => Direction::Left,
+            Self::Right => Direction::Right,
+        };
+        match orientation.to_local(direction) {
+            Direction::Up => Self::Top,
+            Direction::Down => Self::Bottom,
+            Direction::Left => Self::Left,
+            Direction::Right => Self::Right,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum ArrowDirection {
     Entering,
     Leaving,
 }
 
+// The edges of the level at `pos` that face the level at `other`, which is two
+// edges for a level that is diagonally across from it. On a wrapping meta-grid
+// they face the short way around, or both ways if neither is shorter.
+fn edges_facing(
+    pos: MetaGridCoords,
+    other: MetaGridCoords,
+    wrap: Option<(i32, i32)>,
+) -> Vec<BoundaryEdge> {
+    let mut edges = vec![];
+    let (rows, cols) = pos.offset_to(other, wrap);
+    // rows count down the meta-grid
+    if rows.backward {
+        edges.push(BoundaryEdge::Top);
+    }
+    if rows.forward {
+        edges.push(BoundaryEdge::Bottom);
+    }
+    if cols.backward {
+        edges.push(BoundaryEdge::Left);
+    }
+    if cols.forward {
+        edges.push(BoundaryEdge::Right);
+    }
+    edges
+}
+
+fn edge_coords(meta_level: &MetaLevel, edge: BoundaryEdge) -> Vec<GridCoords> {
+    match edge {
+        BoundaryEdge::Top => meta_level.top_boundary_coords().collect(),
+        BoundaryEdge::Bottom => meta_level.bottom_boundary_coords().collect(),
+        BoundaryEdge::Left => meta_level.left_boundary_coords().collect(),
+        BoundaryEdge::Right => meta_level.right_boundary_coords().collect(),
+    }
+}
+
 // ====================
 // ==== COMPONENTS ====
 // ====================
@@ -119,14 +183,15 @@ fn clear_boundary_arrows(
 
 fn update_boundary_arrows_pointing_from(
     current_level: Res<CurrentMetaLevel>,
-    levels: Query<(&Children, &LevelPosition)>,
+    current_puzzle: Res<CurrentPuzzle>,
+    levels: Query<(&Children, &LevelPosition, &SubLevelIndex)>,
     layers: Query<(&LayerMetadata, &TileStorage)>,
     primary_players: Query<Entity, With<PrimaryPlayer>>,
     mut tiles: Query<&mut TileVisible>,
 ) {
-    let (primary_level_children, primary_level_pos) = levels
+    let (primary_level_children, primary_level_pos, primary_sub_level) = levels
         .iter()
-        .find(|(children, _)| {
+        .find(|(children, ..)| {
             children
                 .iter()
                 .any(|&child| primary_players.contains(child))
@@ -150,56 +215,55 @@ fn update_boundary_arrows_pointing_from(
         tile_visible.0 = true;
     };
 
-    // top edge
-    if primary_level_pos.0.row > 0 {
-        current_level
-            .0
-            .top_boundary_coords()
-            .for_each(&mut set_arrow_visible);
-    }
-    // bottom edge
-    if primary_level_pos.0.row < current_level.0.meta_grid_height - 1 {
-        current_level
-            .0
-            .bottom_boundary_coords()
-            .for_each(&mut set_arrow_visible);
-    }
-    // left edge
-    if primary_level_pos.0.col > 0 {
-        current_level
-            .0
-            .left_boundary_coords()
-            .for_each(&mut set_arrow_visible);
+    // point towards every spot in the meta-grid that the primary level
+    // influences, whether or not there is a level there right now
+    let meta_level = &current_level.0;
+    let mut edges = vec![];
+    for edge in (0..meta_level.meta_grid_height)
+        .flat_map(|row| (0..meta_level.meta_grid_width).map(move |col| (row, col)))
+        .map(|(row, col)| MetaGridCoords::new(row, col))
+        .filter(|&pos| {
+            meta_level
+                .influence
+                .connects(pos, primary_level_pos.0, meta_level.wrap())
+        })
+        .flat_map(|pos| edges_facing(primary_level_pos.0, pos, meta_level.wrap()))
+    {
+        if !edges.contains(&edge) {
+            edges.push(edge);
+        }
     }
-    // right edge
-    if primary_level_pos.0.col < current_level.0.meta_grid_width - 1 {
-        current_level
-            .0
-            .right_boundary_coords()
+    let orientation = current_puzzle.0.orientation(primary_sub_level.0);
+    for edge in edges {
+        edge_coords(meta_level, edge.local(orientation))
+            .into_iter()
             .for_each(&mut set_arrow_visible);
     }
 }
 
 fn update_boundary_arrows_pointing_to(
     current_level: Res<CurrentMetaLevel>,
-    levels: Query<(&Children, &LevelPosition)>,
+    current_puzzle: Res<CurrentPuzzle>,
+    levels: Query<(&Children, &LevelPosition, &SubLevelIndex)>,
     layers: Query<(&LayerMetadata, &TileStorage)>,
     primary_players: Query<Entity, With<PrimaryPlayer>>,
     mut tiles: Query<&mut TileVisible>,
 ) {
-    let (_, primary_level_pos) = levels
+    let (_, primary_level_pos, _) = levels
         .iter()
-        .find(|(children, _)| {
+        .find(|(children, ..)| {
             children
                 .iter()
                 .any(|&child| primary_players.contains(child))
         })
         .expect("primary player exists in a level");
 
-    for (level_children, level_pos) in levels
-        .iter()
-        .filter(|(_, level_pos)| level_pos.0.is_neighbor(primary_level_pos.0))
-    {
+    for (level_children, level_pos, sub_level) in levels.iter().filter(|(_, level_pos, _)| {
+        current_level
+            .0
+            .influence
+            .connects(level_pos.0, primary_level_pos.0, current_level.0.wrap())
+    }) {
         let (_, arrows_tile_storage) = level_children
             .iter()
             .filter_map(|&child| layers.get(child).ok())
@@ -215,40 +279,11 @@ fn update_boundary_arrows_pointing_to(
                 .expect("arrow tile matches tile query");
             tile_visible.0 = true;
         };
-        // level is below primary level, so set the top edge visible
-        if level_pos.0.row == primary_level_pos.0.row + 1
-            && level_pos.0.col == primary_level_pos.0.col
-        {
-            current_level
-                .0
-                .top_boundary_coords()
-                .for_each(&mut set_arrow_visible);
-        }
-        // level is above primary level, so set the bottom edge visible
-        if level_pos.0.row == primary_level_pos.0.row - 1
-            && level_pos.0.col == primary_level_pos.0.col
-        {
-            current_level
-                .0
-                .bottom_boundary_coords()
-                .for_each(&mut set_arrow_visible);
-        }
-        // level is to the right of primary level, so set the left edge visible
-        if level_pos.0.row == primary_level_pos.0.row
-            && level_pos.0.col == primary_level_pos.0.col + 1
-        {
-            current_level
-                .0
-                .left_boundary_coords()
-                .for_each(&mut set_arrow_visible);
-        }
-        // level is to the left of primary level, so set the right edge visible
-        if level_pos.0.row == primary_level_pos.0.row
-            && level_pos.0.col == primary_level_pos.0.col - 1
-        {
-            current_level
-                .0
-                .right_boundary_coords()
+        // light up the edges that face the primary level
+        let orientation = current_puzzle.0.orientation(sub_level.0);
+        for edge in edges_facing(level_pos.0, primary_level_pos.0, current_level.0.wrap()) {
+            edge_coords(&current_level.0, edge.local(orientation))
+                .into_iter()
                 .for_each(&mut set_arrow_visible);
         }
     }
@@ -0,0 +1,61 @@
+use crate::{
+    level::CurrentPuzzle,
+    player::{PieceIndex, QueuedMovements},
+    puzzle::Piece,
+    GameState,
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+pub struct BoxPlugin;
+
+impl Plugin for BoxPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_ldtk_entity::<BoxBundle>("Box").add_system(
+            add_box_index
+                .run_if(resource_exists::<CurrentPuzzle>())
+                .in_set(OnUpdate(GameState::InGame)),
+        );
+    }
+}
+
+// ====================
+// ==== COMPONENTS ====
+// ====================
+
+#[derive(Component, Default)]
+pub struct PushableBox;
+
+#[derive(Bundle, LdtkEntity)]
+pub struct BoxBundle {
+    pushable_box: PushableBox,
+    #[grid_coords]
+    grid_coords: GridCoords,
+    #[sprite_sheet_bundle]
+    #[bundle]
+    sprite_sheet: SpriteSheetBundle,
+    #[from_entity_instance]
+    entity_instance: EntityInstance,
+    #[bundle]
+    queued_movements: QueuedMovements,
+}
+
+// =================
+// ==== SYSTEMS ====
+// =================
+
+fn add_box_index(
+    mut commands: Commands,
+    current_puzzle: Res<CurrentPuzzle>,
+    box_query: Query<(Entity, &EntityInstance), Added<PushableBox>>,
+) {
+    for (entity, instance) in &box_query {
+        let index = current_puzzle
+            .0
+            .box_index(&instance.iid)
+            .expect("box iid exists in puzzle");
+        commands
+            .entity(entity)
+            .insert(PieceIndex(Piece::Box(index)));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{level::CurrentPuzzle, GameState, GRID_SIZE};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+const PLATE_COLOR: Color = Color::rgb(0.55, 0.61, 0.71);
+const DOOR_COLOR: Color = Color::rgb(0.35, 0.41, 0.53);
+const OPEN_DOOR_ALPHA: f32 = 0.25;
+
+pub struct DoorPlugin;
+
+impl Plugin for DoorPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_ldtk_entity::<PlateBundle>("Plate")
+            .register_ldtk_entity::<DoorBundle>("Door")
+            .add_systems(
+                (
+                    add_plate_and_door_sprites,
+                    add_door_index.run_if(resource_exists::<CurrentPuzzle>()),
+                    sync_doors.run_if(resource_exists::<CurrentPuzzle>()),
+                )
+                    .in_set(OnUpdate(GameState::InGame)),
+            );
+    }
+}
+
+// ====================
+// ==== COMPONENTS ====
+// ====================
+
+#[derive(Component, Default)]
+pub struct Plate;
+
+#[derive(Bundle, LdtkEntity)]
+pub struct PlateBundle {
+    plate: Plate,
+    #[grid_coords]
+    grid_coords: GridCoords,
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+#[derive(Component, Default)]
+pub struct Door;
+
+// index of the door in `CurrentPuzzle`
+#[derive(Component)]
+pub struct DoorIndex(pub usize);
+
+#[derive(Bundle, LdtkEntity)]
+pub struct DoorBundle {
+    door: Door,
+    #[grid_coords]
+    grid_coords: GridCoords,
+    #[from_entity_instance]
+    entity_instance: EntityInstance,
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+// =================
+// ==== SYSTEMS ====
+// =================
+
+// the tileset has no art for plates and doors, so they're drawn as squares
+fn add_plate_and_door_sprites(
+    mut plates: Query<&mut Sprite, Added<Plate>>,
+    mut doors: Query<&mut Sprite, (Added<Door>, Without<Plate>)>,
+) {
+    for mut sprite in &mut plates {
+        sprite.color = PLATE_COLOR;
+        sprite.custom_size = Some(Vec2::splat(GRID_SIZE as f32 * 0.75));
+    }
+    for mut sprite in &mut doors {
+        sprite.color = DOOR_COLOR;
+        sprite.custom_size = Some(Vec2::splat(GRID_SIZE as f32));
+    }
+}
+
+fn add_door_index(
+    mut commands: Commands,
+    current_puzzle: Res<CurrentPuzzle>,
+    door_query: Query<(Entity, &EntityInstance), Added<Door>>,
+) {
+    for (entity, instance) in &door_query {
+        let index = current_puzzle
+            .0
+            .door_index(&instance.iid)
+            .expect("door iid exists in puzzle");
+        commands.entity(entity).insert(DoorIndex(index));
+    }
+}
+
+fn sync_doors(current_puzzle: Res<CurrentPuzzle>, mut doors: Query<(&DoorIndex, &mut Sprite)>) {
+    for (door_index, mut sprite) in &mut doors {
+        let alpha = if current_puzzle.0.is_door_open(door_index.0) {
+            OPEN_DOOR_ALPHA
+        } else {
+            1.
+        };
+        if sprite.color.a() != alpha {
+            sprite.color.set_a(alpha);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    level::CurrentPuzzle,
+    loading::GameAssets,
+    player::{Direction, PlayerIndex, PrimaryPlayer},
+    puzzle::PuzzleState,
+    solver::{solve, Action, SolveOutcome},
+    GameState, GRID_SIZE,
+};
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashMap,
+};
+use futures_lite::future;
+
+// a hint is only worth waiting a few seconds for
+#[cfg(not(target_arch = "wasm32"))]
+const HINT_MAX_STATES: usize = 1_000_000;
+// the task pool runs on the main thread on the web, so the game freezes until
+// the search is done. this keeps that to a fraction of a second, at the cost of
+// no hint for the bigger levels
+#[cfg(target_arch = "wasm32")]
+const HINT_MAX_STATES: usize = 20_000;
+
+pub struct HintPlugin;
+
+impl Plugin for HintPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HintCount>()
+            .init_resource::<SolutionCache>()
+            .add_event::<HintEvent>()
+            .add_systems(
+                (
+                    reset_hints.run_if(resource_added::<CurrentPuzzle>()),
+                    request_hint.run_if(resource_exists::<CurrentPuzzle>()),
+                    poll_hint_task.run_if(resource_exists::<PendingHint>()),
+                    clear_stale_hint.run_if(resource_exists::<ShownHint>()),
+                    sync_hint_arrow,
+                )
+                    .chain()
+                    .in_set(OnUpdate(GameState::InGame)),
+            );
+    }
+}
+
+// ===================
+// ==== RESOURCES ====
+// ===================
+
+#[derive(Resource, Default)]
+pub struct HintCount(pub i32);
+
+// the next optimal action for every state along the last solution found, so
+// following a hint doesn't require solving the level again
+#[derive(Resource, Default)]
+struct SolutionCache(HashMap<PuzzleState, Action>);
+
+#[derive(Resource)]
+struct PendingHint {
+    puzzle: PuzzleState,
+    task: Task<SolveOutcome>,
+}
+
+#[derive(Resource)]
+pub struct ShownHint {
+    puzzle: PuzzleState,
+    pub action: Action,
+}
+
+// ================
+// ==== EVENTS ====
+// ================
+
+pub struct HintEvent;
+
+// ====================
+// ==== COMPONENTS ====
+// ====================
+
+#[derive(Component)]
+pub struct HintArrow;
+
+// =================
+// ==== SYSTEMS ====
+// =================
+
+fn reset_hints(
+    mut commands: Commands,
+    mut hint_count: ResMut<HintCount>,
+    mut solution_cache: ResMut<SolutionCache>,
+) {
+    hint_count.0 = 0;
+    // cached states belong to the previous level's layout
+    solution_cache.0.clear();
+    commands.remove_resource::<PendingHint>();
+    commands.remove_resource::<ShownHint>();
+}
+
+fn request_hint(
+    mut commands: Commands,
+    mut hint_events: EventReader<HintEvent>,
+    current_puzzle: Res<CurrentPuzzle>,
+    solution_cache: Res<SolutionCache>,
+    pending_hint: Option<Res<PendingHint>>,
+    shown_hint: Option<Res<ShownHint>>,
+    mut hint_count: ResMut<HintCount>,
+) {
+    if hint_events.iter().count() == 0
+        || pending_hint.is_some()
+        || shown_hint.is_some()
+        || current_puzzle.0.is_won()
+        || current_puzzle.0.is_lost()
+    {
+        return;
+    }
+    if let Some(&action) = solution_cache.0.get(&current_puzzle.0) {
+        hint_count.0 += 1;
+        commands.insert_resource(ShownHint {
+            puzzle: current_puzzle.0.clone(),
+            action,
+        });
+        return;
+    }
+    let puzzle = current_puzzle.0.clone();
+    let start = puzzle.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move { solve(&start, HINT_MAX_STATES) });
+    commands.insert_resource(PendingHint { puzzle, task });
+}
+
+fn poll_hint_task(
+    mut commands: Commands,
+    mut pending_hint: ResMut<PendingHint>,
+    current_puzzle: Option<Res<CurrentPuzzle>>,
+    mut solution_cache: ResMut<SolutionCache>,
+    mut hint_count: ResMut<HintCount>,
+) {
+    let Some(outcome) = future::block_on(future::poll_once(&mut pending_hint.task)) else {
+        return;
+    };
+    commands.remove_resource::<PendingHint>();
+    let path = match outcome {
+        SolveOutcome::Solved(path) => path,
+        SolveOutcome::Unsolvable => {
+            info!("no hint: the level can't be solved from here");
+            return;
+        }
+        SolveOutcome::SearchLimitReached => {
+            info!("no hint: the solver gave up");
+            return;
+        }
+    };
+    let mut puzzle = pending_hint.puzzle.clone();
+    for &action in &path {
+        solution_cache.0.insert(puzzle.clone(), action);
+        puzzle.apply_action(action);
+    }
+    // the player may have moved on while the solver was running
+    if current_puzzle.is_none_or(|current| current.0 != pending_hint.puzzle) {
+        return;
+    }
+    if let Some(&action) = path.first() {
+        hint_count.0 += 1;
+        commands.insert_resource(ShownHint {
+            puzzle: pending_hint.puzzle.clone(),
+            action,
+        });
+    }
+}
+
+fn clear_stale_hint(
+    mut commands: Commands,
+    shown_hint: Res<ShownHint>,
+    current_puzzle: Option<Res<CurrentPuzzle>>,
+) {
+    if current_puzzle.is_none_or(|current| current.0 != shown_hint.puzzle) {
+        commands.remove_resource::<ShownHint>();
+    }
+}
+
+fn sync_hint_arrow(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    shown_hint: Option<Res<ShownHint>>,
+    hint_arrows: Query<Entity, With<HintArrow>>,
+    primary_players: Query<Entity, With<PrimaryPlayer>>,
+    players: Query<(Entity, &PlayerIndex)>,
+) {
+    if shown_hint.as_ref().is_some_and(|hint| !hint.is_changed()) {
+        return;
+    }
+    for arrow in &hint_arrows {
+        commands.entity(arrow).despawn_recursive();
+    }
+    // a switch is hinted by an arrow above the player to switch to, pointing
+    // down at it
+    let (player, direction, side) = match shown_hint.map(|hint| hint.action) {
+        Some(Action::Move(direction)) => {
+            let Ok(primary_player) = primary_players.get_single() else {
+                return;
+            };
+            (primary_player, direction, direction)
+        }
+        Some(Action::SwitchPrimary(index)) => {
+            let Some((player, _)) = players.iter().find(|(_, other)| other.0 == index) else {
+                return;
+            };
+            (player, Direction::Down, Direction::Up)
+        }
+        _ => return,
+    };
+    let unit = side.unit_grid_coords();
+    let offset = Vec2::new(unit.x as f32, unit.y as f32) * (GRID_SIZE as f32 * 0.75);
+    let angle = match direction {
+        Direction::Right => 0.,
+        Direction::Up => std::f32::consts::FRAC_PI_2,
+        Direction::Left => std::f32::consts::PI,
+        Direction::Down => -std::f32::consts::FRAC_PI_2,
+    };
+    let arrow = commands
+        .spawn(HintArrow)
+        .insert(Text2dBundle {
+            text: Text::from_section(
+                ">",
+                TextStyle {
+                    font: game_assets.main_font.clone(),
+                    font_size: 48.,
+                    color: Color::rgb(0.9, 0.7, 0.1),
+                },
+            ),
+            // sits just above the player, which is drawn above the level
+            transform: Transform::from_translation(offset.extend(1.))
+                .with_rotation(Quat::from_rotation_z(angle)),
+            ..default()
+        })
+        .id();
+    commands.entity(player).add_child(arrow);
+}
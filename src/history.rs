@@ -0,0 +1,305 @@
+use crate::{
+    level::{
+        CurrentPuzzle, LevelRespawnCountdown, LevelSpawnCountdown, MoveCount, PuzzleRestoredEvent,
+        ReloadLevelEvent,
+    },
+    puzzle::PuzzleState,
+    GameState,
+};
+use bevy::prelude::*;
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<History>()
+            .add_event::<UndoEvent>()
+            .add_event::<RedoEvent>()
+            .add_event::<RewindEvent>()
+            .add_systems(
+                (
+                    undo.run_if(
+                        resource_exists::<CurrentPuzzle>()
+                            .and_then(not(resource_exists::<LevelSpawnCountdown>())),
+                    ),
+                    redo.run_if(
+                        resource_exists::<CurrentPuzzle>()
+                            .and_then(not(resource_exists::<LevelSpawnCountdown>())),
+                    ),
+                    rewind.run_if(
+                        resource_exists::<CurrentPuzzle>()
+                            .and_then(not(resource_exists::<LevelSpawnCountdown>())),
+                    ),
+                )
+                    .in_set(OnUpdate(GameState::InGame)),
+            );
+    }
+}
+
+struct HistoryEntry {
+    puzzle: PuzzleState,
+    move_count: i32,
+}
+
+impl HistoryEntry {
+    // returns the state it replaced
+    fn restore(self, puzzle: &mut PuzzleState, move_count: &mut i32) -> Self {
+        Self {
+            puzzle: std::mem::replace(puzzle, self.puzzle),
+            move_count: std::mem::replace(move_count, self.move_count),
+        }
+    }
+}
+
+// ===================
+// ==== RESOURCES ====
+// ===================
+
+#[derive(Resource, Default)]
+pub struct History {
+    undo_stack: Vec<HistoryEntry>,
+    redo_stack: Vec<HistoryEntry>,
+}
+
+impl History {
+    // records the state from *before* an action. taking a new action throws
+    // away anything that could have been redone.
+    pub fn record(&mut self, puzzle: PuzzleState, move_count: i32) {
+        self.undo_stack.push(HistoryEntry { puzzle, move_count });
+        self.redo_stack.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    // swaps in the state from before the last action, which can then be
+    // redone. false if there is nothing to undo.
+    pub fn undo(&mut self, puzzle: &mut PuzzleState, move_count: &mut i32) -> bool {
+        let Some(entry) = self.undo_stack.pop() else {
+            return false;
+        };
+        let current = entry.restore(puzzle, move_count);
+        self.redo_stack.push(current);
+        true
+    }
+
+    pub fn redo(&mut self, puzzle: &mut PuzzleState, move_count: &mut i32) -> bool {
+        let Some(entry) = self.redo_stack.pop() else {
+            return false;
+        };
+        let current = entry.restore(puzzle, move_count);
+        self.undo_stack.push(current);
+        true
+    }
+
+    // Like an undo, except that it keeps going until no player stands on lava,
+    // and none of it can be redone. A fatal turn can take more than one entry,
+    // e.g. when a teleport follows the move. false if it runs out of entries.
+    pub fn rewind(&mut self, puzzle: &mut PuzzleState, move_count: &mut i32) -> bool {
+        while let Some(entry) = self.undo_stack.pop() {
+            entry.restore(puzzle, move_count);
+            if !puzzle.is_lost() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// ================
+// ==== EVENTS ====
+// ================
+
+pub struct UndoEvent;
+
+pub struct RedoEvent;
+
+// sent instead of reloading the level after stepping on lava, for meta-levels
+// with RewindOnLava set
+pub struct RewindEvent;
+
+// =================
+// ==== SYSTEMS ====
+// =================
+
+fn undo(
+    mut commands: Commands,
+    mut undo_events: EventReader<UndoEvent>,
+    mut history: ResMut<History>,
+    mut current_puzzle: ResMut<CurrentPuzzle>,
+    mut move_count: ResMut<MoveCount>,
+    mut restored_events: EventWriter<PuzzleRestoredEvent>,
+) {
+    for _ in undo_events.iter() {
+        if !history.undo(&mut current_puzzle.0, &mut move_count.0) {
+            continue;
+        }
+        // undoing the move that stepped onto lava cancels the pending reload
+        commands.remove_resource::<LevelRespawnCountdown>();
+        restored_events.send(PuzzleRestoredEvent);
+    }
+}
+
+fn redo(
+    mut redo_events: EventReader<RedoEvent>,
+    mut history: ResMut<History>,
+    mut current_puzzle: ResMut<CurrentPuzzle>,
+    mut move_count: ResMut<MoveCount>,
+    mut restored_events: EventWriter<PuzzleRestoredEvent>,
+) {
+    for _ in redo_events.iter() {
+        if !history.redo(&mut current_puzzle.0, &mut move_count.0) {
+            continue;
+        }
+        restored_events.send(PuzzleRestoredEvent);
+    }
+}
+
+fn rewind(
+    mut commands: Commands,
+    mut rewind_events: EventReader<RewindEvent>,
+    mut history: ResMut<History>,
+    mut current_puzzle: ResMut<CurrentPuzzle>,
+    mut move_count: ResMut<MoveCount>,
+    mut restored_events: EventWriter<PuzzleRestoredEvent>,
+    mut reload_events: EventWriter<ReloadLevelEvent>,
+) {
+    if rewind_events.iter().next().is_none() {
+        return;
+    }
+    rewind_events.clear();
+    commands.remove_resource::<LevelRespawnCountdown>();
+    // a level that starts on lava has nothing to rewind to
+    if history.rewind(&mut current_puzzle.0, &mut move_count.0) {
+        restored_events.send(PuzzleRestoredEvent);
+    } else {
+        reload_events.send(ReloadLevelEvent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        level::{MetaGridCoords, MetaLevel},
+        player::Direction,
+        puzzle::{EntityData, EntityKind, PlayerResponse, SubLevelData},
+        tiles::TileDefinitions,
+    };
+    use bevy::math::IVec2;
+
+    // a single room with the primary player in its left column, two moves
+    // away from lava
+    fn puzzle() -> PuzzleState {
+        let tile_definitions =
+            TileDefinitions::from_ron(include_str!("../assets/levels/beside_yourself.tiles.ron"))
+                .expect("tile definitions parse");
+        let meta_level = MetaLevel {
+            meta_grid_width: 1,
+            meta_grid_height: 1,
+            initial_placement: [(MetaGridCoords::new(0, 0), "a".to_owned())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let sub_level = SubLevelData {
+            iid: "a".to_owned(),
+            width: 5,
+            height: 3,
+            // boundary all around a row of floor that ends in lava
+            int_grid_csv: [[4; 5], [4, 1, 1, 5, 4], [4; 5]].concat(),
+            entities: vec![EntityData {
+                iid: "primary".to_owned(),
+                grid: IVec2::new(1, 1),
+                kind: EntityKind::Player {
+                    primary: true,
+                    response: PlayerResponse::Same,
+                },
+            }],
+        };
+        PuzzleState::from_sub_levels(&meta_level, &[sub_level], &tile_definitions)
+    }
+
+    // records the move the way `try_move_player` does
+    fn apply_move(history: &mut History, puzzle: &mut PuzzleState, move_count: &mut i32) {
+        history.record(puzzle.clone(), *move_count);
+        puzzle
+            .apply_move(Direction::Right)
+            .expect("primary can move");
+        *move_count += 1;
+    }
+
+    #[test]
+    fn undo_and_redo_restore_recorded_states() {
+        let mut history = History::default();
+        let (mut puzzle, mut move_count) = (puzzle(), 0);
+        let start = puzzle.clone();
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        let after_one = puzzle.clone();
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        let after_two = puzzle.clone();
+
+        assert!(history.undo(&mut puzzle, &mut move_count));
+        assert_eq!((&puzzle, move_count), (&after_one, 1));
+        assert!(history.undo(&mut puzzle, &mut move_count));
+        assert_eq!((&puzzle, move_count), (&start, 0));
+        assert!(!history.undo(&mut puzzle, &mut move_count));
+
+        assert!(history.redo(&mut puzzle, &mut move_count));
+        assert_eq!((&puzzle, move_count), (&after_one, 1));
+        assert!(history.redo(&mut puzzle, &mut move_count));
+        assert_eq!((&puzzle, move_count), (&after_two, 2));
+        assert!(!history.redo(&mut puzzle, &mut move_count));
+    }
+
+    #[test]
+    fn a_new_action_can_no_longer_redo() {
+        let mut history = History::default();
+        let (mut puzzle, mut move_count) = (puzzle(), 0);
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        assert!(history.undo(&mut puzzle, &mut move_count));
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        assert!(!history.redo(&mut puzzle, &mut move_count));
+    }
+
+    #[test]
+    fn rewind_undoes_everything_since_stepping_on_lava() {
+        let mut history = History::default();
+        let (mut puzzle, mut move_count) = (puzzle(), 0);
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        let alive = puzzle.clone();
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        assert!(puzzle.is_lost());
+        // another entry on top of the fatal one, like a teleport
+        history.record(puzzle.clone(), move_count);
+        assert!(history.rewind(&mut puzzle, &mut move_count));
+        assert_eq!((&puzzle, move_count), (&alive, 1));
+        assert!(!history.redo(&mut puzzle, &mut move_count));
+    }
+
+    #[test]
+    fn rewind_fails_without_a_state_to_go_back_to() {
+        let mut history = History::default();
+        let (mut puzzle, mut move_count) = (puzzle(), 0);
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        history.clear();
+        // only actions taken while already standing on lava
+        history.record(puzzle.clone(), move_count);
+        assert!(!history.rewind(&mut puzzle, &mut move_count));
+    }
+
+    #[test]
+    fn clear_forgets_everything() {
+        let mut history = History::default();
+        let (mut puzzle, mut move_count) = (puzzle(), 0);
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        apply_move(&mut history, &mut puzzle, &mut move_count);
+        assert!(history.undo(&mut puzzle, &mut move_count));
+        history.clear();
+        assert!(!history.undo(&mut puzzle, &mut move_count));
+        assert!(!history.redo(&mut puzzle, &mut move_count));
+    }
+}
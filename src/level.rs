@@ -1,13 +1,24 @@
 use crate::{
     boundary::BoundaryPlugin,
+    boxes::PushableBox,
+    history::{History, RewindEvent},
     loading::GameAssets,
-    player::{Player, PrimaryPlayer, QueuedInput},
-    ui::{DragUiRoot, Dragging, DragSprite},
+    player::{Direction, Player, PrimaryPlayer, QueuedInput, ResolveTurnSet},
+    puzzle::{Orientation, PuzzleState},
+    tiles::{TileBehavior, TileDefinitions, TileLook},
+    ui::{DragSprite, DragUiRoot, Dragging},
+    util::grid_coords_to_tile_pos,
     GameState, GRID_SIZE, STARTING_LEVEL, Z_OFFSET_PARTICLE, Z_OFFSET_PLAYER,
 };
-use bevy::{prelude::*, render::view::RenderLayers, utils::HashMap};
-use bevy_ecs_ldtk::prelude::*;
+use bevy::{
+    prelude::*,
+    render::view::RenderLayers,
+    utils::{HashMap, HashSet},
+};
+use bevy_ecs_ldtk::{ldtk, prelude::*};
+use bevy_ecs_tilemap::prelude::*;
 use bevy_particle_systems::*;
+use serde::{Deserialize, Serialize};
 
 const LEVEL_SPAWN_DELAY_SEC: f32 = 1.;
 
@@ -17,23 +28,24 @@ impl Plugin for LevelPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<LoadLevelEvent>()
             .add_event::<ReloadLevelEvent>()
+            .add_event::<PuzzleRestoredEvent>()
+            .add_event::<LevelCompleteEvent>()
             .init_resource::<MoveCount>()
-            .register_ldtk_int_cell::<FloorBundle>(1)
-            .register_ldtk_int_cell::<GoalBundle>(2)
-            .register_ldtk_int_cell::<WallBundle>(3)
-            .register_ldtk_int_cell::<BoundaryBundle>(4)
-            .register_ldtk_int_cell::<LavaBundle>(5)
             .add_plugin(BoundaryPlugin)
             .add_systems((setup, prepare_level_data).in_schedule(OnEnter(GameState::InGame)))
             .add_system(cleanup.in_schedule(OnExit(GameState::InGame)))
             .add_systems(
                 (
                     load_level,
+                    add_tile_behaviors,
+                    add_tile_looks,
                     add_particles_to_goals.run_if(resource_exists::<CurrentMetaLevel>()),
                     move_particles_up,
                     move_players_up,
                     reload_level.run_if(resource_exists::<CurrentMetaLevel>()),
                     setup_ldtk_levels_on_spawn.run_if(resource_exists::<CurrentMetaLevel>()),
+                    sync_level_positions.run_if(resource_exists::<CurrentPuzzle>()),
+                    sync_crumbled_tiles.run_if(resource_exists::<CurrentPuzzle>()),
                     // darken_inactive_levels,
                     show_active_boundary_tiles,
                 )
@@ -41,9 +53,20 @@ impl Plugin for LevelPlugin {
             )
             .add_systems(
                 (
-                    check_lava_tiles.run_if(not(resource_exists::<LevelRespawnCountdown>())),
+                    // only look at the puzzle once the conveyors have moved too
+                    check_lava_tiles
+                        .run_if(
+                            resource_exists::<CurrentPuzzle>()
+                                .and_then(not(resource_exists::<LevelRespawnCountdown>())),
+                        )
+                        .after(ResolveTurnSet),
                     update_goal_tile_status,
-                    check_all_goal_tiles.run_if(any_with_component::<Goal>()),
+                    check_all_goal_tiles
+                        .run_if(
+                            resource_exists::<CurrentPuzzle>()
+                                .and_then(any_with_component::<Goal>()),
+                        )
+                        .after(ResolveTurnSet),
                     spawn_level_countdown_timer.run_if(resource_exists::<LevelSpawnCountdown>()),
                     respawn_level_countdown_timer
                         .run_if(resource_exists::<LevelRespawnCountdown>()),
@@ -53,7 +76,7 @@ impl Plugin for LevelPlugin {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MetaGridCoords {
     pub row: i32,
     pub col: i32,
@@ -64,12 +87,109 @@ impl MetaGridCoords {
         Self { row, col }
     }
 
-    pub fn is_neighbor(&self, other: Self) -> bool {
-        (self.row - other.row).abs() + (self.col - other.col).abs() == 1
+    // `wrap` is the number of rows and columns of the meta-grid if its
+    // opposite edges are next to each other, and None if they aren't
+    pub fn is_neighbor(&self, other: Self, wrap: Option<(i32, i32)>) -> bool {
+        let (rows, cols) = self.offset_to(other, wrap);
+        rows.distance + cols.distance == 1
+    }
+
+    // how many rows and columns away `other` is, going the short way around
+    // a wrapping meta-grid
+    pub fn offset_to(&self, other: Self, wrap: Option<(i32, i32)>) -> (AxisOffset, AxisOffset) {
+        (
+            AxisOffset::new(self.row, other.row, wrap.map(|(rows, _)| rows)),
+            AxisOffset::new(self.col, other.col, wrap.map(|(_, cols)| cols)),
+        )
+    }
+
+    // brings coords that went off one edge of a wrapping meta-grid back in on
+    // the opposite edge
+    pub fn wrapped(self, wrap: Option<(i32, i32)>) -> Self {
+        match wrap {
+            Some((rows, cols)) => Self::new(self.row.rem_euclid(rows), self.col.rem_euclid(cols)),
+            None => self,
+        }
+    }
+}
+
+// The distance between two slots along one axis of the meta-grid, and which
+// way is shorter. When the meta-grid wraps, both ways can be just as short.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AxisOffset {
+    pub distance: i32,
+    pub backward: bool,
+    pub forward: bool,
+}
+
+impl AxisOffset {
+    fn new(from: i32, to: i32, wrap: Option<i32>) -> Self {
+        let Some(size) = wrap else {
+            return Self {
+                distance: (to - from).abs(),
+                backward: to < from,
+                forward: to > from,
+            };
+        };
+        let forward = (to - from).rem_euclid(size);
+        let backward = (from - to).rem_euclid(size);
+        let distance = forward.min(backward);
+        Self {
+            distance,
+            backward: distance > 0 && backward == distance,
+            forward: distance > 0 && forward == distance,
+        }
+    }
+}
+
+// Which sub-levels have their players move along with the primary player's
+// sub-level. Set by the Influence field on any of the sub-levels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Influence {
+    #[default]
+    Orthogonal,
+    OrthogonalAndDiagonal,
+    SameRow,
+    SameColumn,
+    All,
+}
+
+impl Influence {
+    fn from_field(value: &str) -> Option<Self> {
+        match value {
+            "Orthogonal" => Some(Self::Orthogonal),
+            "OrthogonalAndDiagonal" => Some(Self::OrthogonalAndDiagonal),
+            "SameRow" => Some(Self::SameRow),
+            "SameColumn" => Some(Self::SameColumn),
+            "All" => Some(Self::All),
+            _ => None,
+        }
+    }
+
+    // a sub-level never influences itself
+    pub fn connects(
+        self,
+        pos: MetaGridCoords,
+        other: MetaGridCoords,
+        wrap: Option<(i32, i32)>,
+    ) -> bool {
+        if pos == other {
+            return false;
+        }
+        match self {
+            Self::Orthogonal => pos.is_neighbor(other, wrap),
+            Self::OrthogonalAndDiagonal => {
+                let (rows, cols) = pos.offset_to(other, wrap);
+                rows.distance <= 1 && cols.distance <= 1
+            }
+            Self::SameRow => pos.row == other.row,
+            Self::SameColumn => pos.col == other.col,
+            Self::All => true,
+        }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct MetaLevel {
     pub level_num: i32,
     pub meta_grid_width: i32,
@@ -77,9 +197,39 @@ pub struct MetaLevel {
     pub level_grid_width: i32,
     pub level_grid_height: i32,
     pub initial_placement: HashMap<MetaGridCoords, String>,
+    pub par: Option<i32>,
+    // whether players can walk off the edge of one sub-level into the next
+    pub cross_boundaries: bool,
+    // whether stepping on lava only undoes the fatal turn, instead of
+    // reloading the whole meta-level
+    pub rewind_on_lava: bool,
+    pub influence: Influence,
+    // sub-levels that can't be dragged, and that nothing can be dropped onto.
+    // they never move, so their initial position is enough
+    pub locked: HashSet<MetaGridCoords>,
+    // whether sub-levels can only slide into an empty neighboring slot, like a
+    // sliding tile puzzle, instead of being swapped with any other slot
+    pub sliding: bool,
+    // whether sub-levels can also be rotated and mirrored
+    pub rotatable: bool,
+    // how often the player can switch which player is primary, 0 for not at
+    // all and -1 for as often as they like
+    pub primary_switches: i32,
+    // whether the leftmost and rightmost columns of the meta-grid are next to
+    // each other, and the top and bottom rows too
+    pub wraparound: bool,
 }
 
 impl MetaLevel {
+    pub fn is_locked(&self, pos: MetaGridCoords) -> bool {
+        self.locked.contains(&pos)
+    }
+
+    pub fn wrap(&self) -> Option<(i32, i32)> {
+        self.wraparound
+            .then_some((self.meta_grid_height, self.meta_grid_width))
+    }
+
     pub fn level_width_px(&self) -> i32 {
         self.level_grid_width * GRID_SIZE
     }
@@ -128,6 +278,25 @@ impl MetaLevel {
         Vec2::new(col_offset, row_offset) - center_offset
     }
 
+    // turned sub-levels are turned around their center, which stays put
+    pub fn get_transform(&self, grid_pos: MetaGridCoords, orientation: Orientation) -> Transform {
+        let center_offset = Vec3::new(
+            self.level_width_px() as f32,
+            self.level_height_px() as f32,
+            0.,
+        ) / 2.;
+        let rotation =
+            Quat::from_rotation_z(-std::f32::consts::FRAC_PI_2 * orientation.quarter_turns as f32);
+        let scale = Vec3::new(if orientation.flipped { -1. } else { 1. }, 1., 1.);
+        let translation = self.get_translation(grid_pos).extend(0.) + center_offset
+            - rotation * (scale * center_offset);
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
     pub fn top_boundary_coords(&self) -> impl Iterator<Item = GridCoords> + '_ {
         (1..(self.level_grid_width - 1)).map(|x| GridCoords::new(x, self.level_grid_height - 1))
     }
@@ -145,6 +314,19 @@ impl MetaLevel {
             .map(move |y| GridCoords::new(self.level_grid_width - 1, y))
     }
 
+    // three stars for matching par, two for up to half as many moves again, and
+    // one for anything else. levels without a par aren't rated.
+    pub fn star_rating(&self, move_count: i32) -> Option<u8> {
+        let par = self.par?;
+        Some(if move_count <= par {
+            3
+        } else if move_count * 2 <= par * 3 {
+            2
+        } else {
+            1
+        })
+    }
+
     pub fn grid_coords_to_translation(&self, grid_coords: GridCoords) -> Vec2 {
         let x = grid_coords.x * GRID_SIZE;
         let y = grid_coords.y * GRID_SIZE;
@@ -152,6 +334,116 @@ impl MetaLevel {
     }
 }
 
+// Groups the LDtk levels into meta-levels by their LevelNum field, stopping at
+// the first LevelNum that has no levels.
+pub fn build_meta_levels<'a>(levels: impl IntoIterator<Item = &'a ldtk::Level>) -> Vec<MetaLevel> {
+    let levels = levels.into_iter().collect::<Vec<_>>();
+    (0..)
+        .map_while(|level_num| build_meta_level(level_num, levels.iter().copied()))
+        .collect()
+}
+
+pub fn build_meta_level<'a>(
+    level_num: i32,
+    levels: impl IntoIterator<Item = &'a ldtk::Level>,
+) -> Option<MetaLevel> {
+    // these are updated as we iterate over the levels
+    let mut meta_grid_width = 1;
+    let mut meta_grid_height = 1;
+    let mut level_grid_width = 0;
+    let mut level_grid_height = 0;
+    let mut initial_placement = HashMap::new();
+    let mut par = None;
+    let mut cross_boundaries = false;
+    let mut rewind_on_lava = false;
+    let mut influence = None;
+    let mut locked = HashSet::new();
+    let mut sliding = false;
+    let mut rotatable = false;
+    let mut primary_switches = None;
+    let mut wraparound = false;
+
+    for level in levels
+        .into_iter()
+        // only include the levels with the correct LevelNum
+        .filter(|level| int_field(level, "LevelNum") == Some(level_num))
+    {
+        let row = int_field(level, "GridRow").expect("GridRow field is defined");
+        let col = int_field(level, "GridCol").expect("GridCol field is defined");
+        // Par is optional and only needs to be set on one of the levels
+        par = par.or_else(|| int_field(level, "Par"));
+        // the flags below are set on a single level too
+        cross_boundaries |= bool_field(level, "CrossBoundaries");
+        rewind_on_lava |= bool_field(level, "RewindOnLava");
+        influence = influence.or_else(|| {
+            level
+                .field_instances
+                .iter()
+                .find_map(|field| match &field.value {
+                    FieldValue::Enum(Some(value)) if field.identifier == "Influence" => {
+                        Influence::from_field(value)
+                    }
+                    _ => None,
+                })
+        });
+        if bool_field(level, "Locked") {
+            locked.insert(MetaGridCoords::new(row, col));
+        }
+        sliding |= bool_field(level, "Sliding");
+        rotatable |= bool_field(level, "Rotatable");
+        wraparound |= bool_field(level, "Wraparound");
+        // the meta-grid can be made bigger than its sub-levels need, to leave
+        // empty slots along the bottom or right
+        meta_grid_width = meta_grid_width.max(int_field(level, "MetaGridWidth").unwrap_or(0));
+        meta_grid_height = meta_grid_height.max(int_field(level, "MetaGridHeight").unwrap_or(0));
+        primary_switches = primary_switches.or_else(|| int_field(level, "PrimarySwitches"));
+        meta_grid_height = meta_grid_height.max(row + 1);
+        meta_grid_width = meta_grid_width.max(col + 1);
+        level_grid_width = level_grid_width.max(level.px_wid / GRID_SIZE);
+        level_grid_height = level_grid_height.max(level.px_hei / GRID_SIZE);
+        initial_placement.insert(MetaGridCoords::new(row, col), level.iid.clone());
+    }
+
+    if initial_placement.is_empty() {
+        return None;
+    }
+
+    Some(MetaLevel {
+        level_num,
+        meta_grid_width,
+        meta_grid_height,
+        level_grid_width,
+        level_grid_height,
+        initial_placement,
+        par,
+        cross_boundaries,
+        rewind_on_lava,
+        influence: influence.unwrap_or_default(),
+        locked,
+        sliding,
+        rotatable,
+        primary_switches: primary_switches.unwrap_or(0),
+        wraparound,
+    })
+}
+
+pub fn int_field(level: &ldtk::Level, identifier: &str) -> Option<i32> {
+    level
+        .field_instances
+        .iter()
+        .find_map(|field| match &field.value {
+            FieldValue::Int(Some(val)) if field.identifier == identifier => Some(*val),
+            _ => None,
+        })
+}
+
+// unset and missing flags are both off
+pub fn bool_field(level: &ldtk::Level, identifier: &str) -> bool {
+    level.field_instances.iter().any(|field| {
+        field.identifier == identifier && matches!(field.value, FieldValue::Bool(true))
+    })
+}
+
 // ===================
 // ==== RESOURCES ====
 // ===================
@@ -165,6 +457,9 @@ pub struct AllMetaLevels(Vec<MetaLevel>);
 #[derive(Resource)]
 pub struct CurrentMetaLevel(pub MetaLevel);
 
+#[derive(Resource)]
+pub struct CurrentPuzzle(pub PuzzleState);
+
 #[derive(Resource)]
 pub struct LevelSpawnCountdown {
     pub timer: Timer,
@@ -186,6 +481,17 @@ pub struct LoadLevelEvent {
 
 pub struct ReloadLevelEvent;
 
+pub struct LevelCompleteEvent {
+    pub level_num: i32,
+    pub move_count: i32,
+    pub par: Option<i32>,
+    pub stars: Option<u8>,
+}
+
+// sent when `CurrentPuzzle` is replaced wholesale (e.g. by undo) rather than
+// by a single move or swap, so every entity has to be re-synced with it
+pub struct PuzzleRestoredEvent;
+
 // ====================
 // ==== COMPONENTS ====
 // ====================
@@ -196,88 +502,36 @@ pub struct LevelPosition(pub MetaGridCoords);
 #[derive(Component)]
 pub struct IsActive(pub bool);
 
+// index of the sub-level in `CurrentPuzzle`
 #[derive(Component)]
-pub enum TileType {
-    Floor,
-    Goal,
-    Wall,
-    Boundary,
-    Lava,
-}
-
-impl From<IntGridCell> for TileType {
-    fn from(int_grid_cell: IntGridCell) -> Self {
-        match int_grid_cell.value {
-            1 => Self::Floor,
-            2 => Self::Goal,
-            3 => Self::Wall,
-            4 => Self::Boundary,
-            5 => Self::Lava,
-            _ => panic!("unknown tile type"),
-        }
-    }
-}
+pub struct SubLevelIndex(pub usize);
 
-#[derive(Bundle, LdtkIntCell)]
-struct GameTileBundle {
-    #[from_int_grid_cell]
-    pub tile_type: TileType,
-}
+// Every IntGrid tile gets a `TileBehavior` from the tile definitions, plus a
+// marker for each flag that is set, or `Floor` if none are.
 
 #[derive(Component, Default)]
 pub struct Floor;
 
-#[derive(Bundle, LdtkIntCell)]
-pub struct FloorBundle {
-    pub floor: Floor,
-    #[from_int_grid_cell]
-    pub tile_type: TileType,
-}
-
 #[derive(Component, Default)]
 pub struct Goal {
     pub activated: bool,
 }
 
-#[derive(Bundle, LdtkIntCell)]
-pub struct GoalBundle {
-    pub goal: Goal,
-    #[from_int_grid_cell]
-    pub tile_type: TileType,
-}
-
 #[derive(Component)]
 pub struct GoalParticles;
 
 #[derive(Component, Default)]
 pub struct Wall;
 
-#[derive(Bundle, LdtkIntCell)]
-pub struct WallBundle {
-    pub wall: Wall,
-    #[from_int_grid_cell]
-    pub tile_type: TileType,
-}
-
 #[derive(Component, Default)]
 pub struct Boundary;
 
-#[derive(Bundle, LdtkIntCell)]
-pub struct BoundaryBundle {
-    pub boundary: Boundary,
-    #[from_int_grid_cell]
-    pub tile_type: TileType,
-}
-
 #[derive(Component, Default)]
 pub struct Lava;
 
-#[derive(Bundle, LdtkIntCell)]
-pub struct LavaBundle {
-    pub lava: Lava,
-    #[from_int_grid_cell]
-    pub tile_type: TileType,
-}
+// gets a `Lava` marker as well once the floor crumbles
+#[derive(Component, Default)]
+pub struct CrackedFloor;
 
 // =================
 // ==== SYSTEMS ====
@@ -325,66 +579,145 @@ fn prepare_level_data(
     game_assets: Res<GameAssets>,
     ldtk_assets: Res<Assets<LdtkAsset>>,
 ) {
-    let mut all_levels = AllMetaLevels(vec![]);
     let ldtk_asset = ldtk_assets
         .get(&game_assets.levels)
         .expect("LDtk asset exists");
-    for level_num in 0.. {
-        // these are updated as we iterate over the levels
-        let mut meta_grid_width = 1;
-        let mut meta_grid_height = 1;
-        let mut level_grid_width = 0;
-        let mut level_grid_height = 0;
-        let mut initial_placement = HashMap::new();
-
-        for level in ldtk_asset
-            .iter_levels()
-            // only include the levels with the correct LevelNum
-            .filter(|level| {
-                level.field_instances.iter().any(|field| {
-                    field.identifier == "LevelNum"
-                        && matches!(field.value, FieldValue::Int(Some(num)) if num == level_num)
-                })
-            })
-        {
-            let row = level
-                .field_instances
-                .iter()
-                .find_map(|field| match (&field.identifier, &field.value) {
-                    (ident, FieldValue::Int(Some(val))) if ident == "GridRow" => Some(*val),
-                    _ => None,
-                })
-                .expect("GridRow field is defined");
-            let col = level
-                .field_instances
-                .iter()
-                .find_map(|field| match (&field.identifier, &field.value) {
-                    (ident, FieldValue::Int(Some(val))) if ident == "GridCol" => Some(*val),
-                    _ => None,
-                })
-                .expect("GridRow field is defined");
-            meta_grid_height = meta_grid_height.max(row + 1);
-            meta_grid_width = meta_grid_width.max(col + 1);
-            level_grid_width = level_grid_width.max(level.px_wid / GRID_SIZE);
-            level_grid_height = level_grid_height.max(level.px_hei / GRID_SIZE);
-            initial_placement.insert(MetaGridCoords::new(row, col), level.iid.clone());
+    let all_levels = AllMetaLevels(build_meta_levels(ldtk_asset.iter_levels()));
+    commands.insert_resource(all_levels);
+}
+
+fn add_tile_behaviors(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    tile_definitions: Res<Assets<TileDefinitions>>,
+    int_grid_cells: Query<(Entity, &IntGridCell), Added<IntGridCell>>,
+) {
+    if int_grid_cells.is_empty() {
+        return;
+    }
+    let tile_definitions = tile_definitions
+        .get(&game_assets.tiles)
+        .expect("tile definitions exist");
+    for (entity, &IntGridCell { value }) in &int_grid_cells {
+        let Some(behavior) = tile_definitions.behavior(value) else {
+            error!("IntGrid value {value} has no tile definition, so the tile is left empty");
+            continue;
+        };
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.insert(behavior);
+        if behavior == TileBehavior::default() {
+            entity_commands.insert(Floor);
+        }
+        if behavior.goal {
+            entity_commands.insert(Goal::default());
+        }
+        if behavior.solid && !behavior.boundary {
+            entity_commands.insert(Wall);
+        }
+        if behavior.boundary {
+            entity_commands.insert(Boundary);
+        }
+        if behavior.deadly {
+            entity_commands.insert(Lava);
         }
+        if behavior.crumbles_into.is_some() {
+            entity_commands.insert(CrackedFloor);
+        }
+    }
+}
 
-        if initial_placement.is_empty() {
-            break;
+// Redraws the tiles whose definition gives them a look of their own, like ice,
+// and turns the arrows drawn on conveyors and one-way gates to point the way
+// they go.
+fn add_tile_looks(
+    game_assets: Res<GameAssets>,
+    tile_definitions: Res<Assets<TileDefinitions>>,
+    int_grid_cells: Query<(&GridCoords, &Parent, &IntGridCell), Added<IntGridCell>>,
+    layer_parents: Query<&Parent, With<LayerMetadata>>,
+    levels: Query<&Children>,
+    layers: Query<(&LayerMetadata, &TileStorage)>,
+    mut rendered_tiles: Query<(&mut TileTextureIndex, &mut TileColor)>,
+    mut arrow_tiles: Query<&mut TileFlip>,
+) {
+    if int_grid_cells.is_empty() {
+        return;
+    }
+    let tile_definitions = tile_definitions
+        .get(&game_assets.tiles)
+        .expect("tile definitions exist");
+    for (&grid_coords, parent, &IntGridCell { value }) in &int_grid_cells {
+        let (Some(behavior), Some(look)) = (
+            tile_definitions.behavior(value),
+            tile_definitions.look(value),
+        ) else {
+            continue;
+        };
+        let level = layer_parents
+            .get(parent.get())
+            .expect("tile parent is a layer")
+            .get();
+        let Ok(level_children) = levels.get(level) else {
+            continue;
+        };
+        if look != TileLook::default() {
+            if let Some(Ok((mut tile_texture, mut tile_color))) =
+                rendered_tile(level_children, "Tiles", grid_coords, &layers)
+                    .map(|tile| rendered_tiles.get_mut(tile))
+            {
+                if let Some(index) = look.index {
+                    tile_texture.0 = index;
+                }
+                tile_color.0 = look.color();
+            }
+        }
+        if let Some(direction) = behavior.conveyor.or(behavior.one_way) {
+            if let Some(Ok(mut tile_flip)) =
+                rendered_tile(level_children, "TileArrows", grid_coords, &layers)
+                    .map(|tile| arrow_tiles.get_mut(tile))
+            {
+                *tile_flip = arrow_flip(direction);
+            }
         }
+    }
+}
 
-        all_levels.0.push(MetaLevel {
-            level_num,
-            meta_grid_width,
-            meta_grid_height,
-            level_grid_width,
-            level_grid_height,
-            initial_placement,
-        });
+// The arrows in the tileset point right. They are turned rather than mirrored,
+// which comes out the same whichever diagonal the `d` flip is along.
+fn arrow_flip(direction: Direction) -> TileFlip {
+    match direction {
+        Direction::Up => TileFlip {
+            y: true,
+            d: true,
+            ..default()
+        },
+        Direction::Down => TileFlip {
+            x: true,
+            d: true,
+            ..default()
+        },
+        Direction::Left => TileFlip {
+            x: true,
+            y: true,
+            ..default()
+        },
+        Direction::Right => TileFlip::default(),
     }
+}
 
-    commands.insert_resource(all_levels);
+// the tile drawn for `grid_coords` in one of a level's tile layers, if there is
+// one
+fn rendered_tile(
+    level_children: &Children,
+    layer_identifier: &str,
+    grid_coords: GridCoords,
+    layers: &Query<(&LayerMetadata, &TileStorage)>,
+) -> Option<Entity> {
+    let (_, tile_storage) = level_children
+        .iter()
+        .filter_map(|&child| layers.get(child).ok())
+        .find(|(metadata, _)| metadata.identifier == layer_identifier)?;
+    let tile_pos = grid_coords_to_tile_pos(grid_coords).expect("tile coords are in bounds");
+    tile_storage.get(&tile_pos)
 }
 
 fn add_particles_to_goals(
@@ -431,11 +764,15 @@ fn add_particles_to_goals(
 fn load_level(
     mut commands: Commands,
     mut state: ResMut<NextState<GameState>>,
+    game_assets: Res<GameAssets>,
+    ldtk_assets: Res<Assets<LdtkAsset>>,
+    tile_definitions: Res<Assets<TileDefinitions>>,
     all_levels: Res<AllMetaLevels>,
     mut ldtk_world_query: Query<&mut LevelSet>,
     mut event_reader: EventReader<LoadLevelEvent>,
     mut queued_input: ResMut<QueuedInput>,
     mut move_count: ResMut<MoveCount>,
+    mut history: ResMut<History>,
 ) {
     if let Some(event) = event_reader.iter().next() {
         commands.remove_resource::<LevelSpawnCountdown>();
@@ -443,10 +780,22 @@ fn load_level(
         commands.remove_resource::<Dragging>();
         move_count.0 = 0;
         queued_input.0.clear();
+        history.clear();
 
         let mut level_set = ldtk_world_query.single_mut();
         if let Some(meta_level) = all_levels.0.get(event.level_num as usize) {
             level_set.iids = meta_level.initial_placement.values().cloned().collect();
+            let ldtk_asset = ldtk_assets
+                .get(&game_assets.levels)
+                .expect("LDtk asset exists");
+            let tile_definitions = tile_definitions
+                .get(&game_assets.tiles)
+                .expect("tile definitions exist");
+            commands.insert_resource(CurrentPuzzle(PuzzleState::from_ldtk_levels(
+                meta_level,
+                ldtk_asset.iter_levels(),
+                tile_definitions,
+            )));
             commands.insert_resource(CurrentMetaLevel(meta_level.clone()));
         } else {
             state.set(GameState::GameOver);
@@ -469,6 +818,7 @@ fn reload_level(
         let mut level_set = ldtk_world_query.single_mut();
         level_set.iids.clear();
         commands.remove_resource::<CurrentMetaLevel>();
+        commands.remove_resource::<CurrentPuzzle>();
         load_events.send(LoadLevelEvent {
             level_num: current_level_num,
         });
@@ -479,6 +829,7 @@ fn reload_level(
 fn setup_ldtk_levels_on_spawn(
     mut commands: Commands,
     current_level: Res<CurrentMetaLevel>,
+    current_puzzle: Res<CurrentPuzzle>,
     ldtk_level_assets: Res<Assets<LdtkLevel>>,
     mut ldtk_level_query: Query<
         (Entity, &Children, &Handle<LdtkLevel>, &mut Transform),
@@ -496,42 +847,128 @@ fn setup_ldtk_levels_on_spawn(
             .iter()
             .find(|(_pos, iid)| **iid == ldtk_level.level.iid)
             .expect("level iid exists in active level");
+        let sub_level_index = current_puzzle
+            .0
+            .sub_level_index(&ldtk_level.level.iid)
+            .expect("level iid exists in puzzle");
         let is_active = level_children
             .iter()
             .any(|child| primary_players.contains(*child));
         commands
             .entity(level_entity)
             .insert(LevelPosition(grid_pos))
+            .insert(SubLevelIndex(sub_level_index))
             .insert(IsActive(is_active));
-        level_transform.translation = current_level.0.get_translation(grid_pos).extend(0.);
+        *level_transform = current_level
+            .0
+            .get_transform(grid_pos, current_puzzle.0.orientation(sub_level_index));
     }
 }
 
-fn check_lava_tiles(
+fn sync_level_positions(
+    current_level: Res<CurrentMetaLevel>,
+    current_puzzle: Res<CurrentPuzzle>,
+    mut restored_events: EventReader<PuzzleRestoredEvent>,
+    mut levels: Query<(&SubLevelIndex, &mut LevelPosition, &mut Transform)>,
+) {
+    if restored_events.iter().next().is_none() {
+        return;
+    }
+    restored_events.clear();
+    for (sub_level_index, mut level_pos, mut transform) in &mut levels {
+        let grid_pos = current_puzzle.0.position(sub_level_index.0);
+        *level_pos = LevelPosition(grid_pos);
+        *transform = current_level
+            .0
+            .get_transform(grid_pos, current_puzzle.0.orientation(sub_level_index.0));
+    }
+}
+
+// Mirrors crumbled floor from `CurrentPuzzle`, including floor that is restored
+// again by an undo.
+fn sync_crumbled_tiles(
     mut commands: Commands,
-    lavas: Query<(&Parent, &GridCoords), With<Lava>>,
-    players: Query<(&Parent, &GridCoords), With<Player>>,
-    layers: Query<&Parent, With<LayerMetadata>>,
+    current_puzzle: Res<CurrentPuzzle>,
+    game_assets: Res<GameAssets>,
+    tile_definitions: Res<Assets<TileDefinitions>>,
+    mut cracked_tiles: Query<
+        (
+            Entity,
+            &GridCoords,
+            &Parent,
+            &IntGridCell,
+            &mut TileBehavior,
+        ),
+        With<CrackedFloor>,
+    >,
+    layer_parents: Query<&Parent, With<LayerMetadata>>,
+    levels: Query<(&SubLevelIndex, &Children)>,
+    layers: Query<(&LayerMetadata, &TileStorage)>,
+    mut rendered_tiles: Query<(&mut TileTextureIndex, &mut TileColor)>,
 ) {
-    for (lava_parent, lava_coords) in &lavas {
-        let layer_parent = layers
-            .get(lava_parent.get())
-            .expect("goal's parent is a layer");
-        let lava_level = layer_parent.get();
-        if players.iter().any(|(player_parent, player_coords)| {
-            player_parent.get() == lava_level && player_coords == lava_coords
-        }) {
-            println!("asd");
-            commands.insert_resource(LevelRespawnCountdown {
-                timer: Timer::from_seconds(LEVEL_SPAWN_DELAY_SEC, TimerMode::Once),
-            });
+    let tile_definitions = tile_definitions
+        .get(&game_assets.tiles)
+        .expect("tile definitions exist");
+    for (entity, &grid_coords, parent, int_grid_cell, mut behavior) in &mut cracked_tiles {
+        let level = layer_parents
+            .get(parent.get())
+            .expect("tile parent is a layer")
+            .get();
+        let Ok((&SubLevelIndex(sub_level), level_children)) = levels.get(level) else {
+            continue;
+        };
+        let cracked = tile_definitions
+            .behavior(int_grid_cell.value)
+            .expect("cracked floor has a tile definition");
+        // crumbled floor behaves and looks like the value it crumbles into
+        let value = match cracked.crumbles_into {
+            Some(value) if current_puzzle.0.is_crumbled(sub_level, grid_coords) => value,
+            _ => int_grid_cell.value,
+        };
+        let (Some(new_behavior), Some(look)) = (
+            tile_definitions.behavior(value),
+            tile_definitions.look(value),
+        ) else {
+            continue;
+        };
+        if *behavior != new_behavior {
+            *behavior = new_behavior;
+            if new_behavior.deadly {
+                commands.entity(entity).insert(Lava);
+            } else {
+                commands.entity(entity).remove::<Lava>();
+            }
+        }
+
+        let Some(Ok((mut tile_texture, mut tile_color))) =
+            rendered_tile(level_children, "Tiles", grid_coords, &layers)
+                .map(|tile| rendered_tiles.get_mut(tile))
+        else {
+            continue;
+        };
+        if let Some(index) = look.index {
+            if tile_texture.0 != index {
+                tile_texture.0 = index;
+            }
+        }
+        let color = look.color();
+        if tile_color.0 != color {
+            tile_color.0 = color;
         }
     }
 }
 
+fn check_lava_tiles(mut commands: Commands, current_puzzle: Res<CurrentPuzzle>) {
+    if current_puzzle.0.is_lost() {
+        commands.insert_resource(LevelRespawnCountdown {
+            timer: Timer::from_seconds(LEVEL_SPAWN_DELAY_SEC, TimerMode::Once),
+        });
+    }
+}
+
 fn update_goal_tile_status(
     mut goals: Query<(&Parent, &GridCoords, &mut Goal)>,
-    players: Query<(&Parent, &GridCoords), With<Player>>,
+    players: Query<(&Parent, &GridCoords), Or<(With<Player>, With<PushableBox>)>>,
     layers: Query<&Parent, With<LayerMetadata>>,
 ) {
     for (goal_parent, goal_coords, mut goal) in &mut goals {
@@ -548,20 +985,28 @@ fn update_goal_tile_status(
 fn check_all_goal_tiles(
     mut commands: Commands,
     current_level: Res<CurrentMetaLevel>,
+    current_puzzle: Res<CurrentPuzzle>,
+    move_count: Res<MoveCount>,
     level_spawn_countdown: Option<Res<LevelSpawnCountdown>>,
-    goal_query: Query<&Goal>,
     goal_particles: Query<Entity, With<GoalParticles>>,
+    mut complete_events: EventWriter<LevelCompleteEvent>,
 ) {
     // only continue if we're not already waiting to load a new level
     if level_spawn_countdown.is_some() {
         return;
     }
-    if goal_query.iter().all(|goal| goal.activated) {
+    if current_puzzle.0.is_won() {
         println!("done!!!");
         commands.insert_resource(LevelSpawnCountdown {
             timer: Timer::from_seconds(LEVEL_SPAWN_DELAY_SEC, TimerMode::Once),
             level_num: current_level.0.level_num + 1,
         });
+        complete_events.send(LevelCompleteEvent {
+            level_num: current_level.0.level_num,
+            move_count: move_count.0,
+            par: current_level.0.par,
+            stars: current_level.0.star_rating(move_count.0),
+        });
         for goal_particles in &goal_particles {
             commands.entity(goal_particles).insert(Playing);
         }
@@ -582,15 +1027,21 @@ fn spawn_level_countdown_timer(
 
 fn respawn_level_countdown_timer(
     time: Res<Time>,
+    current_level: Option<Res<CurrentMetaLevel>>,
     mut countdown: ResMut<LevelRespawnCountdown>,
     mut reload_level_events: EventWriter<ReloadLevelEvent>,
+    mut rewind_events: EventWriter<RewindEvent>,
 ) {
     if countdown.timer.tick(time.delta()).just_finished() {
-        reload_level_events.send(ReloadLevelEvent);
+        if current_level.is_some_and(|level| level.0.rewind_on_lava) {
+            rewind_events.send(RewindEvent);
+        } else {
+            reload_level_events.send(ReloadLevelEvent);
+        }
     }
 }
 
-fn move_players_up(mut particles: Query<&mut Transform, Added<Player>>) {
+fn move_players_up(mut particles: Query<&mut Transform, Or<(Added<Player>, Added<PushableBox>)>>) {
     for mut transform in &mut particles {
         transform.translation.z = Z_OFFSET_PLAYER;
     }
@@ -663,3 +1114,29 @@ fn show_active_boundary_tiles(
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_rating_compares_the_move_count_to_par() {
+        let level = MetaLevel {
+            par: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(level.star_rating(9), Some(3));
+        assert_eq!(level.star_rating(10), Some(3));
+        assert_eq!(level.star_rating(11), Some(2));
+        assert_eq!(level.star_rating(15), Some(2));
+        assert_eq!(level.star_rating(16), Some(1));
+        // half as many moves again isn't a whole number of moves for an odd par
+        let odd_par = MetaLevel {
+            par: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(odd_par.star_rating(7), Some(2));
+        assert_eq!(odd_par.star_rating(8), Some(1));
+        assert_eq!(MetaLevel::default().star_rating(0), None);
+    }
+}
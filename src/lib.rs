@@ -1,10 +1,21 @@
 pub mod boundary;
+pub mod boxes;
+pub mod doors;
+pub mod hint;
+pub mod history;
 pub mod level;
 pub mod loading;
 pub mod menu;
 pub mod player;
+pub mod progress;
+pub mod puzzle;
+pub mod replay;
+pub mod solver;
+pub mod teleporters;
+pub mod tiles;
 pub mod ui;
 pub mod util;
+pub mod validation;
 
 use bevy::{prelude::*, render::view::RenderLayers};
 use bevy_ecs_ldtk::prelude::*;
@@ -60,12 +71,22 @@ impl Plugin for GamePlugin {
             // game stuff
             .add_state::<GameState>()
             .add_plugin(util::UtilPlugin)
+            .add_plugin(tiles::TilePlugin)
             .add_plugin(loading::LoadingPlugin)
             .add_plugin(menu::MenuPlugin)
             .add_plugin(level::LevelPlugin)
             .add_plugin(player::PlayerPlugin)
+            .add_plugin(boxes::BoxPlugin)
+            .add_plugin(doors::DoorPlugin)
+            .add_plugin(teleporters::TeleporterPlugin)
             .add_plugin(ui::UiPlugin)
+            .add_plugin(history::HistoryPlugin)
+            .add_plugin(hint::HintPlugin)
+            .add_plugin(progress::ProgressPlugin)
+            .add_plugin(replay::ReplayPlugin)
             .add_system(setup_camera.on_startup());
+        #[cfg(feature = "dev")]
+        app.add_plugin(validation::ValidationPlugin);
     }
 }
 
@@ -1,4 +1,4 @@
-use crate::GameState;
+use crate::{tiles::TileDefinitions, GameState};
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
 
@@ -17,6 +17,8 @@ impl Plugin for LoadingPlugin {
 pub struct GameAssets {
     #[asset(path = "levels/beside_yourself.ldtk")]
     pub levels: Handle<bevy_ecs_ldtk::LdtkAsset>,
+    #[asset(path = "levels/beside_yourself.tiles.ron")]
+    pub tiles: Handle<TileDefinitions>,
     #[asset(path = "fonts/Kenney Pixel.ttf")]
     pub main_font: Handle<Font>,
     #[asset(path = "px.png")]
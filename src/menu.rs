@@ -80,6 +80,8 @@ fn setup_main_menu(mut commands: Commands, game_assets: Res<GameAssets>) {
                         "The primary player affects players in the surrounding levels.",
                         "Get all players to the goal to move to the next stage.",
                         "Press R to reset a  if you get stuck.",
+                        "Press Z to undo a move or swap, and Y to redo it.",
+                        "Press H for a hint.",
                         "Good luck!",
                     ];
                     for line in lines {
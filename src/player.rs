@@ -1,21 +1,31 @@
 use std::{collections::VecDeque, time::Duration};
 
 use crate::{
+    hint::HintEvent,
+    history::{History, RedoEvent, UndoEvent},
     level::{
-        CurrentMetaLevel, Goal, IsActive, Lava, LevelPosition, LevelRespawnCountdown,
-        LevelSpawnCountdown, MetaGridCoords, MoveCount, ReloadLevelEvent, TileType,
+        CurrentMetaLevel, CurrentPuzzle, Goal, IsActive, Lava, LevelRespawnCountdown,
+        LevelSpawnCountdown, MetaGridCoords, MoveCount, PuzzleRestoredEvent, ReloadLevelEvent,
+        SubLevelIndex,
     },
-    util::grid_coords_to_tile_pos,
+    puzzle::{Motion, MoveResult, Piece},
+    replay::ReplayPlayback,
     GameState, GRID_SIZE,
 };
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
-use bevy_ecs_tilemap::tiles::TileStorage;
 use bevy_tweening::{lens::TransformPositionLens, *};
 use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
 
 const MOVEMENT_TIME_SEC: f32 = 0.1;
+const TELEPORT_FADE_SEC: f32 = 0.15;
+const DEATH_FADE_SEC: f32 = 0.5;
 const NEIGHBOR_DELAY_SEC: f32 = 0.;
+// let everyone finish walking before the conveyors move them
+const CONVEYOR_DELAY_SEC: f32 = MOVEMENT_TIME_SEC;
+// and before the teleporters send them off
+const TELEPORT_DELAY_SEC: f32 = MOVEMENT_TIME_SEC;
 
 const PLAYER_HAPPY_INDEX: usize = 30;
 const PLAYER_NEUTRAL_INDEX: usize = 31;
@@ -30,12 +40,24 @@ impl Plugin for PlayerPlugin {
             .register_ldtk_entity::<PlayerBundle>("Player")
             .add_event::<TryMovePlayerEvent>()
             .add_event::<TryMoveNeighboringPlayersEvent>()
+            .add_event::<SwitchPrimaryEvent>()
+            .add_system(component_animator_system::<TextureAtlasSprite>)
             .add_systems(
                 (
-                    reload_level_input,
+                    reload_level_input.run_if(not(resource_exists::<ReplayPlayback>())),
+                    hint_input.run_if(not(resource_exists::<ReplayPlayback>())),
+                    history_input.run_if(not(resource_exists::<ReplayPlayback>())),
                     add_components_to_primary_player,
+                    add_player_index.run_if(resource_exists::<CurrentPuzzle>()),
+                    sync_piece_positions.run_if(resource_exists::<CurrentPuzzle>()),
                     unlock_player_movement,
                     player_face,
+                    play_death_animation.run_if(
+                        resource_exists::<CurrentPuzzle>()
+                            .and_then(resource_exists::<LevelRespawnCountdown>()),
+                    ),
+                    revive_players,
+                    keep_pieces_upright,
                 )
                     .in_set(OnUpdate(GameState::InGame)),
             )
@@ -44,19 +66,37 @@ impl Plugin for PlayerPlugin {
                     send_try_move_event_on_input.run_if(
                         any_with_component::<PrimaryPlayer>()
                             .and_then(not(resource_exists::<LevelSpawnCountdown>()))
-                            .and_then(not(resource_exists::<LevelRespawnCountdown>())),
+                            .and_then(not(resource_exists::<LevelRespawnCountdown>()))
+                            .and_then(not(resource_exists::<ReplayPlayback>())),
                     ),
-                    try_move_player,
-                    try_move_neighboring_players,
+                    try_move_player.in_set(ResolveTurnSet),
+                    try_move_neighboring_players.in_set(ResolveTurnSet),
                     process_queued_movement,
                 )
                     .chain()
                     .distributive_run_if(resource_exists::<CurrentMetaLevel>())
                     .in_set(OnUpdate(GameState::InGame)),
+            )
+            .add_systems(
+                (
+                    switch_primary_input.run_if(
+                        not(resource_exists::<ReplayPlayback>())
+                            .and_then(not(resource_exists::<LevelRespawnCountdown>())),
+                    ),
+                    switch_primary,
+                    sync_primary_player.run_if(resource_exists_and_changed::<CurrentPuzzle>()),
+                )
+                    .chain()
+                    .distributive_run_if(resource_exists::<CurrentPuzzle>())
+                    .in_set(OnUpdate(GameState::InGame)),
             );
     }
 }
 
+// the systems that apply a move to `CurrentPuzzle`
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ResolveTurnSet;
+
 #[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug)]
 enum PlayerAction {
     MoveUp,
@@ -64,9 +104,13 @@ enum PlayerAction {
     MoveLeft,
     MoveRight,
     ResetLevel,
+    Hint,
+    Undo,
+    Redo,
+    SwitchPrimary,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Down,
@@ -75,16 +119,7 @@ pub enum Direction {
 }
 
 impl Direction {
-    fn unit_vec(&self) -> Vec2 {
-        match self {
-            Self::Up => Vec2::Y,
-            Self::Down => -Vec2::Y,
-            Self::Right => Vec2::X,
-            Self::Left => -Vec2::X,
-        }
-    }
-
-    fn unit_grid_coords(&self) -> GridCoords {
+    pub fn unit_grid_coords(&self) -> GridCoords {
         match self {
             Self::Up => GridCoords::new(0, 1),
             Self::Down => GridCoords::new(0, -1),
@@ -92,13 +127,52 @@ impl Direction {
             Self::Left => GridCoords::new(-1, 0),
         }
     }
+
+    // a quarter turn clockwise
+    pub fn rotated_clockwise(&self) -> Self {
+        match self {
+            Self::Up => Self::Right,
+            Self::Right => Self::Down,
+            Self::Down => Self::Left,
+            Self::Left => Self::Up,
+        }
+    }
+
+    // left and right are swapped
+    pub fn mirrored(&self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+            _ => *self,
+        }
+    }
 }
 
+// Movements only start once the piece is done with its earlier ones, so they
+// go to where the piece ends up rather than by how far it moves.
 struct QueuedMovement {
-    direction: Direction,
+    // the sub-level the piece is in afterwards
+    sub_level: usize,
+    // the center of the tile it ends up on, within that sub-level
+    target: Vec2,
+    motion: Motion,
     delay: Timer,
 }
 
+// fades players when they teleport or die
+struct TextureAtlasSpriteColorLens {
+    start: Color,
+    end: Color,
+}
+
+impl Lens<TextureAtlasSprite> for TextureAtlasSpriteColorLens {
+    fn lerp(&mut self, target: &mut TextureAtlasSprite, ratio: f32) {
+        let start: Vec4 = self.start.into();
+        let end: Vec4 = self.end.into();
+        target.color = start.lerp(end, ratio).into();
+    }
+}
+
 // ===================
 // ==== RESOURCES ====
 // ===================
@@ -116,10 +190,17 @@ pub struct TryMovePlayerEvent {
 }
 
 pub struct TryMoveNeighboringPlayersEvent {
+    // the index of the player that moved, in `CurrentPuzzle`
+    pub player: usize,
     pub grid_coords: MetaGridCoords,
     pub direction: Direction,
 }
 
+// makes the player with this index in `CurrentPuzzle` the primary player
+pub struct SwitchPrimaryEvent {
+    pub player: usize,
+}
+
 // ====================
 // ==== COMPONENTS ====
 // ====================
@@ -133,9 +214,35 @@ pub struct PrimaryPlayer;
 #[derive(Component)]
 pub struct IsMoving;
 
+// a player that has stepped on lava and is fading away
+#[derive(Component)]
+pub struct Dying;
+
+// index of the player in `CurrentPuzzle`
+#[derive(Component)]
+pub struct PlayerIndex(pub usize);
+
+// which piece of `CurrentPuzzle` a player or box entity mirrors
+#[derive(Component)]
+pub struct PieceIndex(pub Piece);
+
 #[derive(Component, Default)]
 pub struct QueuedMovements(VecDeque<QueuedMovement>);
 
+// the entities that mirror players and boxes in `CurrentPuzzle`
+type PieceQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static PieceIndex,
+        &'static Parent,
+        &'static mut Transform,
+        &'static mut GridCoords,
+        &'static mut QueuedMovements,
+    ),
+>;
+
 #[derive(Bundle, LdtkEntity)]
 pub struct PlayerBundle {
     player: Player,
@@ -165,25 +272,122 @@ fn add_components_to_primary_player(
             commands
                 .entity(entity)
                 .insert(PrimaryPlayer)
-                .insert(InputManagerBundle {
-                    action_state: ActionState::default(),
-                    input_map: InputMap::new([
-                        (KeyCode::Left, PlayerAction::MoveLeft),
-                        (KeyCode::Right, PlayerAction::MoveRight),
-                        (KeyCode::Up, PlayerAction::MoveUp),
-                        (KeyCode::Down, PlayerAction::MoveDown),
-                        (KeyCode::A, PlayerAction::MoveLeft),
-                        (KeyCode::D, PlayerAction::MoveRight),
-                        (KeyCode::W, PlayerAction::MoveUp),
-                        (KeyCode::S, PlayerAction::MoveDown),
-                        (KeyCode::R, PlayerAction::ResetLevel),
-                    ]),
-                    ..default()
-                });
+                .insert(primary_player_input());
         }
     }
 }
 
+fn primary_player_input() -> InputManagerBundle<PlayerAction> {
+    InputManagerBundle {
+        action_state: ActionState::default(),
+        input_map: InputMap::new([
+            (KeyCode::Left, PlayerAction::MoveLeft),
+            (KeyCode::Right, PlayerAction::MoveRight),
+            (KeyCode::Up, PlayerAction::MoveUp),
+            (KeyCode::Down, PlayerAction::MoveDown),
+            (KeyCode::A, PlayerAction::MoveLeft),
+            (KeyCode::D, PlayerAction::MoveRight),
+            (KeyCode::W, PlayerAction::MoveUp),
+            (KeyCode::S, PlayerAction::MoveDown),
+            (KeyCode::R, PlayerAction::ResetLevel),
+            (KeyCode::H, PlayerAction::Hint),
+            (KeyCode::Z, PlayerAction::Undo),
+            (KeyCode::Y, PlayerAction::Redo),
+            (KeyCode::Tab, PlayerAction::SwitchPrimary),
+        ]),
+        ..default()
+    }
+}
+
+// cycles through the players that the primary player can be switched to
+fn switch_primary_input(
+    current_puzzle: Res<CurrentPuzzle>,
+    primary_players: Query<&ActionState<PlayerAction>, With<PrimaryPlayer>>,
+    mut switch_events: EventWriter<SwitchPrimaryEvent>,
+) {
+    for action_state in &primary_players {
+        if action_state.just_pressed(PlayerAction::SwitchPrimary) {
+            let primary = current_puzzle.0.primary();
+            let player_count = current_puzzle.0.players().len();
+            if let Some(player) = (1..player_count)
+                .map(|offset| (primary + offset) % player_count)
+                .find(|&player| current_puzzle.0.can_switch_primary(player))
+            {
+                switch_events.send(SwitchPrimaryEvent { player });
+            }
+        }
+    }
+}
+
+fn switch_primary(
+    mut switch_events: EventReader<SwitchPrimaryEvent>,
+    mut current_puzzle: ResMut<CurrentPuzzle>,
+    mut queued_input: ResMut<QueuedInput>,
+    mut move_count: ResMut<MoveCount>,
+    mut history: ResMut<History>,
+) {
+    for event in switch_events.iter() {
+        let before = current_puzzle.0.clone();
+        if !current_puzzle.0.apply_switch_primary(event.player) {
+            continue;
+        }
+        history.record(before, move_count.0);
+        move_count.0 += 1;
+        // moves queued up for the old primary player shouldn't carry over
+        queued_input.0.clear();
+    }
+}
+
+// Moves `PrimaryPlayer` and its input to whichever player `CurrentPuzzle` has
+// as primary, after a switch or after undoing one. The boundary arrows follow
+// the `PrimaryPlayer` component by themselves.
+fn sync_primary_player(
+    mut commands: Commands,
+    current_puzzle: Res<CurrentPuzzle>,
+    players: Query<(
+        Entity,
+        &PlayerIndex,
+        &EntityInstance,
+        Option<&PrimaryPlayer>,
+    )>,
+) {
+    for (entity, player_index, instance, maybe_primary) in &players {
+        // the previous level's players linger for a moment after loading
+        if current_puzzle.0.player_index(&instance.iid) != Some(player_index.0) {
+            continue;
+        }
+        let is_primary = player_index.0 == current_puzzle.0.primary();
+        if is_primary && maybe_primary.is_none() {
+            commands
+                .entity(entity)
+                .insert(PrimaryPlayer)
+                .insert(primary_player_input());
+        } else if !is_primary && maybe_primary.is_some() {
+            commands
+                .entity(entity)
+                .remove::<PrimaryPlayer>()
+                .remove::<InputManagerBundle<PlayerAction>>();
+        }
+    }
+}
+
+fn add_player_index(
+    mut commands: Commands,
+    current_puzzle: Res<CurrentPuzzle>,
+    player_query: Query<(Entity, &EntityInstance), Added<Player>>,
+) {
+    for (entity, instance) in &player_query {
+        let index = current_puzzle
+            .0
+            .player_index(&instance.iid)
+            .expect("player iid exists in puzzle");
+        commands
+            .entity(entity)
+            .insert(PlayerIndex(index))
+            .insert(PieceIndex(Piece::Player(index)));
+    }
+}
+
 fn reload_level_input(
     mut reload_events: EventWriter<ReloadLevelEvent>,
     primary_players: Query<&ActionState<PlayerAction>, With<PrimaryPlayer>>,
@@ -195,6 +399,32 @@ fn reload_level_input(
     }
 }
 
+fn hint_input(
+    mut hint_events: EventWriter<HintEvent>,
+    primary_players: Query<&ActionState<PlayerAction>, With<PrimaryPlayer>>,
+) {
+    for action_state in &primary_players {
+        if action_state.just_pressed(PlayerAction::Hint) {
+            hint_events.send(HintEvent);
+        }
+    }
+}
+
+fn history_input(
+    mut undo_events: EventWriter<UndoEvent>,
+    mut redo_events: EventWriter<RedoEvent>,
+    primary_players: Query<&ActionState<PlayerAction>, With<PrimaryPlayer>>,
+) {
+    for action_state in &primary_players {
+        if action_state.just_pressed(PlayerAction::Undo) {
+            undo_events.send(UndoEvent);
+        }
+        if action_state.just_pressed(PlayerAction::Redo) {
+            redo_events.send(RedoEvent);
+        }
+    }
+}
+
 fn send_try_move_event_on_input(
     mut queued_input: ResMut<QueuedInput>,
     primary_players: Query<
@@ -237,15 +467,17 @@ fn unlock_player_movement(
             &Parent,
             &Animator<Transform>,
             Option<&PrimaryPlayer>,
+            Option<&Player>,
         ),
         With<IsMoving>,
     >,
     mut levels: Query<&mut IsActive>,
 ) {
-    for (entity, parent, animator, maybe_primary) in &mut players {
+    for (entity, parent, animator, maybe_primary, maybe_player) in &mut players {
         if animator.tweenable().progress() == 1. {
             commands.entity(entity).remove::<IsMoving>();
-            if maybe_primary.is_none() {
+            // boxes are moved along with players too, but don't affect the level
+            if maybe_player.is_some() && maybe_primary.is_none() {
                 let mut is_active = levels
                     .get_mut(parent.get())
                     .expect("player parent is a level");
@@ -258,37 +490,119 @@ fn unlock_player_movement(
 fn process_queued_movement(
     mut commands: Commands,
     time: Res<Time>,
-    mut entities: Query<(Entity, &Transform, &mut QueuedMovements)>,
+    mut entities: Query<(Entity, &Parent, &mut Transform, &mut QueuedMovements), Without<IsMoving>>,
+    levels: Query<(Entity, &SubLevelIndex, &GlobalTransform)>,
 ) {
-    for (entity, transform, mut queued_movements) in &mut entities {
-        let mut to_remove = vec![];
-        for (i, movement) in queued_movements.0.iter_mut().enumerate() {
-            if !movement.delay.tick(time.delta()).just_finished() {
-                continue;
+    // an entity's movements play one after another, e.g. a step and then a
+    // conveyor
+    for (entity, parent, mut transform, mut queued_movements) in &mut entities {
+        let Some(movement) = queued_movements.0.front_mut() else {
+            continue;
+        };
+        if !movement.delay.tick(time.delta()).just_finished() {
+            continue;
+        }
+        move_to_sub_level(
+            &mut commands,
+            entity,
+            parent.get(),
+            &mut transform,
+            movement.sub_level,
+            &levels,
+        );
+        let lens = TransformPositionLens {
+            start: transform.translation,
+            end: movement.target.extend(transform.translation.z),
+        };
+        match movement.motion {
+            // a slide across ice is a single tween, not one per tile
+            Motion::Step { distance, .. } => {
+                let tween = Tween::new(
+                    EaseFunction::QuadraticInOut,
+                    Duration::from_secs_f32(MOVEMENT_TIME_SEC * distance as f32),
+                    lens,
+                );
+                commands
+                    .entity(entity)
+                    .insert((IsMoving, Animator::new(tween)));
+            }
+            // fade out, jump to the partner while invisible, and fade back in
+            Motion::Teleport => {
+                let fade = Duration::from_secs_f32(TELEPORT_FADE_SEC);
+                let jump = Tween::new(EaseMethod::Discrete(0.5), fade * 2, lens);
+                let fade_out = Tween::new(
+                    EaseFunction::QuadraticIn,
+                    fade,
+                    TextureAtlasSpriteColorLens {
+                        start: Color::WHITE,
+                        end: Color::rgba(1., 1., 1., 0.),
+                    },
+                );
+                let fade_in = Tween::new(
+                    EaseFunction::QuadraticOut,
+                    fade,
+                    TextureAtlasSpriteColorLens {
+                        start: Color::rgba(1., 1., 1., 0.),
+                        end: Color::WHITE,
+                    },
+                );
+                commands.entity(entity).insert((
+                    IsMoving,
+                    Animator::new(jump),
+                    Animator::new(fade_out.then(fade_in)),
+                ));
             }
-            to_remove.push(i);
-            let delta = movement.direction.unit_vec().extend(0.) * GRID_SIZE as f32;
-            let tween = Tween::new(
-                EaseFunction::QuadraticInOut,
-                Duration::from_secs_f32(MOVEMENT_TIME_SEC),
-                TransformPositionLens {
-                    start: transform.translation,
-                    end: transform.translation + delta,
-                },
-            );
-            commands
-                .entity(entity)
-                .insert((IsMoving, Animator::new(tween)));
         }
-        for i in to_remove.into_iter().rev() {
-            queued_movements.0.remove(i);
+        queued_movements.0.pop_front();
+    }
+}
+
+// waits for the fatal move to finish before fading out
+fn play_death_animation(
+    mut commands: Commands,
+    current_puzzle: Res<CurrentPuzzle>,
+    players: Query<(Entity, &PlayerIndex), (Without<IsMoving>, Without<Dying>)>,
+) {
+    for (entity, &PlayerIndex(player_index)) in &players {
+        let player = current_puzzle.0.players()[player_index];
+        if !current_puzzle.0.is_deadly(player.sub_level, player.coords) {
+            continue;
         }
+        let tween = Tween::new(
+            EaseFunction::QuadraticIn,
+            Duration::from_secs_f32(DEATH_FADE_SEC),
+            TextureAtlasSpriteColorLens {
+                start: Color::WHITE,
+                end: Color::rgba(1., 0.2, 0.2, 0.),
+            },
+        );
+        commands
+            .entity(entity)
+            .insert((Dying, Animator::new(tween)));
+    }
+}
+
+// undoing or rewinding the fatal turn brings the player back
+fn revive_players(
+    mut commands: Commands,
+    mut restored_events: EventReader<PuzzleRestoredEvent>,
+    mut dying_players: Query<(Entity, &mut TextureAtlasSprite), With<Dying>>,
+) {
+    if restored_events.iter().next().is_none() {
+        return;
+    }
+    restored_events.clear();
+    for (entity, mut sprite) in &mut dying_players {
+        sprite.color = Color::WHITE;
+        commands
+            .entity(entity)
+            .remove::<(Dying, Animator<TextureAtlasSprite>)>();
     }
 }
 
 fn player_face(
     mut players: Query<(&Parent, &mut TextureAtlasSprite, &GridCoords), With<Player>>,
-    goals: Query<(&Parent, &Goal)>,
+    goals: Query<(&Parent, &GridCoords), With<Goal>>,
     lavas: Query<(&Parent, &GridCoords), With<Lava>>,
     layers: Query<&Parent, With<LayerMetadata>>,
 ) {
@@ -302,16 +616,14 @@ fn player_face(
             player_sprite.index = PLAYER_UNHAPPY_INDEX;
             continue;
         }
-        let (_, goal) = goals
-            .iter()
-            .find(|(goal_parent, _)| {
-                let layer_parent = layers
-                    .get(goal_parent.get())
-                    .expect("goal parent is a layer");
-                layer_parent.get() == player_parent.get()
-            })
-            .expect("every level with a player has a goal");
-        if goal.activated {
+        // a level can have several goals (or none), so only look at the one
+        // the player is standing on
+        if goals.iter().any(|(goal_parent, goal_coords)| {
+            let layer_parent = layers
+                .get(goal_parent.get())
+                .expect("goal parent is a layer");
+            layer_parent.get() == player_parent.get() && player_coords == goal_coords
+        }) {
             player_sprite.index = PLAYER_HAPPY_INDEX;
         } else {
             player_sprite.index = PLAYER_NEUTRAL_INDEX;
@@ -322,44 +634,27 @@ fn player_face(
 fn try_move_player(
     mut move_player_events: EventReader<TryMovePlayerEvent>,
     mut move_neighboring_players_events: EventWriter<TryMoveNeighboringPlayersEvent>,
-    players: Query<(Entity, &Parent), With<PrimaryPlayer>>,
-    levels: Query<(Entity, &Children, &LevelPosition)>,
-    layers: Query<(&LayerMetadata, &TileStorage)>,
-    tiles: Query<&TileType>,
-    mut grid_coords: Query<&mut GridCoords>,
-    mut queued_movements: Query<&mut QueuedMovements>,
+    mut current_puzzle: ResMut<CurrentPuzzle>,
+    player_indices: Query<&PlayerIndex>,
+    mut pieces: PieceQuery,
     mut move_count: ResMut<MoveCount>,
+    mut history: ResMut<History>,
 ) {
     for event in move_player_events.iter() {
-        let (player_entity, parent) = players.get(event.player).unwrap();
-        let (_, level_children, level_pos) = levels.get(parent.get()).unwrap();
-        let (_, tile_storage) = level_children
-            .iter()
-            .filter_map(|&child| layers.get(child).ok())
-            .find(|(metadata, _)| metadata.identifier == "TileData")
-            .expect("TileData layer exists");
-
-        let did_move = player_movement_logic(
-            &tile_storage,
-            &tiles,
-            player_entity,
-            &event.direction,
-            &mut grid_coords,
-        );
+        let &PlayerIndex(player_index) = player_indices
+            .get(event.player)
+            .expect("moving player has a puzzle index");
+        let before = current_puzzle.0.clone();
+        let did_move = current_puzzle.0.move_player(player_index, event.direction);
         if let Some(move_results) = did_move {
-            for result in move_results {
-                queued_movements
-                    .get_mut(result.entity)
-                    .expect("entity can queue movements")
-                    .0
-                    .push_back(QueuedMovement {
-                        direction: result.direction,
-                        delay: Timer::from_seconds(0., TimerMode::Once),
-                    });
-            }
+            history.record(before, move_count.0);
+            queue_move_results(&move_results, &mut pieces, 0.);
             move_count.0 += 1;
             move_neighboring_players_events.send(TryMoveNeighboringPlayersEvent {
-                grid_coords: level_pos.0,
+                player: player_index,
+                grid_coords: current_puzzle
+                    .0
+                    .position(current_puzzle.0.players()[player_index].sub_level),
                 direction: event.direction,
             })
         }
@@ -368,76 +663,147 @@ fn try_move_player(
 
 fn try_move_neighboring_players(
     mut move_neighboring_player_events: EventReader<TryMoveNeighboringPlayersEvent>,
-    mut player_query: Query<Entity, With<Player>>,
-    mut levels: Query<(&Children, &LevelPosition, &mut IsActive), With<Handle<LdtkLevel>>>,
-    layers: Query<(&LayerMetadata, &TileStorage)>,
-    tiles: Query<&TileType>,
-    mut grid_coords: Query<&mut GridCoords>,
-    mut queued_movements: Query<&mut QueuedMovements>,
+    mut current_puzzle: ResMut<CurrentPuzzle>,
+    mut pieces: PieceQuery,
+    move_count: Res<MoveCount>,
+    mut history: ResMut<History>,
 ) {
     for event in move_neighboring_player_events.iter() {
-        for (level_children, _, mut _level_is_active) in levels
-            .iter_mut()
-            .filter(|(_, level_pos, _)| level_pos.0.is_neighbor(event.grid_coords))
-        {
-            let (_, tile_storage) = level_children
-                .iter()
-                .filter_map(|&child| layers.get(child).ok())
-                .find(|(metadata, _)| metadata.identifier == "TileData")
-                .expect("TileData layer exists");
-            for &child in level_children.iter() {
-                if let Ok(player) = player_query.get_mut(child) {
-                    let did_move = player_movement_logic(
-                        &tile_storage,
-                        &tiles,
-                        player,
-                        &event.direction,
-                        &mut grid_coords,
-                    );
-                    if let Some(move_results) = did_move {
-                        for result in move_results {
-                            queued_movements
-                                .get_mut(result.entity)
-                                .expect("entity can queue movements")
-                                .0
-                                .push_back(QueuedMovement {
-                                    direction: result.direction,
-                                    delay: Timer::from_seconds(NEIGHBOR_DELAY_SEC, TimerMode::Once),
-                                });
-                        }
-                    }
-                }
-            }
+        let move_results = current_puzzle
+            .0
+            .move_neighboring_players(event.grid_coords, event.direction);
+        queue_move_results(&move_results, &mut pieces, NEIGHBOR_DELAY_SEC);
+        // the conveyors run once everyone else has moved
+        let conveyor_results = current_puzzle.0.move_players_on_conveyors();
+        queue_move_results(&conveyor_results, &mut pieces, CONVEYOR_DELAY_SEC);
+        // and the teleporters last. a teleport can be undone on its own, so it
+        // gets a history entry of its own, without counting as another move
+        let moved = move_results
+            .iter()
+            .chain(&conveyor_results)
+            .filter_map(|result| match result.piece {
+                Piece::Player(player) => Some(player),
+                Piece::Box(_) => None,
+            })
+            .chain([event.player])
+            .collect::<Vec<_>>();
+        let before = current_puzzle.0.clone();
+        let teleport_results = current_puzzle.0.move_players_on_teleporters(&moved);
+        if !teleport_results.is_empty() {
+            history.record(before, move_count.0);
+            queue_move_results(&teleport_results, &mut pieces, TELEPORT_DELAY_SEC);
         }
     }
 }
 
-struct MoveResult {
+fn sync_piece_positions(
+    mut commands: Commands,
+    current_puzzle: Res<CurrentPuzzle>,
+    mut restored_events: EventReader<PuzzleRestoredEvent>,
+    mut queued_input: ResMut<QueuedInput>,
+    mut pieces: PieceQuery,
+    levels: Query<(Entity, &SubLevelIndex, &GlobalTransform)>,
+) {
+    if restored_events.iter().next().is_none() {
+        return;
+    }
+    restored_events.clear();
+    queued_input.0.clear();
+    for (entity, piece_index, parent, mut transform, mut grid_coords, mut queued_movements) in
+        &mut pieces
+    {
+        *grid_coords = current_puzzle.0.piece_coords(piece_index.0);
+        queued_movements.0.clear();
+        move_to_sub_level(
+            &mut commands,
+            entity,
+            parent.get(),
+            &mut transform,
+            current_puzzle.0.piece_sub_level(piece_index.0),
+            &levels,
+        );
+        let tween = Tween::new(
+            EaseFunction::QuadraticInOut,
+            Duration::from_secs_f32(MOVEMENT_TIME_SEC),
+            TransformPositionLens {
+                start: transform.translation,
+                end: grid_coords_to_center(*grid_coords).extend(transform.translation.z),
+            },
+        );
+        commands
+            .entity(entity)
+            .insert((IsMoving, Animator::new(tween)));
+    }
+}
+
+// pieces turn along with their sub-level as they move around it, but their
+// sprites stay upright
+fn keep_pieces_upright(
+    levels: Query<&Transform, With<SubLevelIndex>>,
+    mut pieces: Query<(&Parent, &mut Transform), (With<PieceIndex>, Without<SubLevelIndex>)>,
+) {
+    for (parent, mut transform) in &mut pieces {
+        let Ok(level_transform) = levels.get(parent.get()) else {
+            continue;
+        };
+        let (scale, rotation, _) = level_transform
+            .compute_matrix()
+            .inverse()
+            .to_scale_rotation_translation();
+        if transform.rotation != rotation || transform.scale != scale {
+            transform.rotation = rotation;
+            transform.scale = scale;
+        }
+    }
+}
+
+// player and box sprites are anchored at the center of their tile
+fn grid_coords_to_center(grid_coords: GridCoords) -> Vec2 {
+    (IVec2::new(grid_coords.x, grid_coords.y) * GRID_SIZE).as_vec2()
+        + Vec2::splat(GRID_SIZE as f32 / 2.)
+}
+
+// Re-parents a piece to the level entity of a sub-level, without moving it on
+// screen.
+fn move_to_sub_level(
+    commands: &mut Commands,
     entity: Entity,
-    direction: Direction,
-}
-
-fn player_movement_logic(
-    tile_storage: &TileStorage,
-    tiles: &Query<&TileType>,
-    player_entity: Entity,
-    direction: &Direction,
-    grid_coords: &mut Query<&mut GridCoords>,
-) -> Option<Vec<MoveResult>> {
-    let old_coords = *grid_coords
-        .get(player_entity)
-        .expect("player has GridCoords");
-    let new_coords = old_coords + direction.unit_grid_coords();
-    let new_tile_pos = grid_coords_to_tile_pos(new_coords)?;
-    let tile_entity = tile_storage.checked_get(&new_tile_pos)?;
-    let tile_type = tiles.get(tile_entity).expect("tile entity is a tile");
-    match tile_type {
-        TileType::Wall | TileType::Boundary => return None,
-        _ => {}
+    parent: Entity,
+    transform: &mut Transform,
+    sub_level: usize,
+    levels: &Query<(Entity, &SubLevelIndex, &GlobalTransform)>,
+) {
+    let (_, &SubLevelIndex(current_sub_level), current_level_transform) =
+        levels.get(parent).expect("piece parent is a level");
+    if current_sub_level == sub_level {
+        return;
+    }
+    let (level_entity, _, level_transform) = levels
+        .iter()
+        .find(|(_, index, _)| index.0 == sub_level)
+        .expect("every sub-level has a level entity");
+    // the two levels may be turned differently
+    let translation = current_level_transform.transform_point(transform.translation);
+    transform.translation = level_transform
+        .affine()
+        .inverse()
+        .transform_point3(translation);
+    commands.entity(level_entity).add_child(entity);
+}
+
+// mirror the puzzle's move results onto the player and box entities
+fn queue_move_results(move_results: &[MoveResult], pieces: &mut PieceQuery, delay_sec: f32) {
+    for result in move_results {
+        let (.., mut grid_coords, mut queued_movements) = pieces
+            .iter_mut()
+            .find(|(_, index, ..)| index.0 == result.piece)
+            .expect("moved piece has an entity");
+        *grid_coords = result.coords;
+        queued_movements.0.push_back(QueuedMovement {
+            sub_level: result.sub_level,
+            target: grid_coords_to_center(result.coords),
+            motion: result.motion,
+            delay: Timer::from_seconds(delay_sec, TimerMode::Once),
+        });
     }
-    *grid_coords.get_mut(player_entity).unwrap() = new_coords;
-    Some(vec![MoveResult {
-        entity: player_entity,
-        direction: *direction,
-    }])
 }
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+
+use crate::{level::LevelCompleteEvent, GameState};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_arch = "wasm32"))]
+const PROGRESS_PATH: &str = "progress.ron";
+
+pub struct ProgressPlugin;
+
+impl Plugin for ProgressPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Progress::load())
+            .add_system(record_progress.in_set(OnUpdate(GameState::InGame)));
+    }
+}
+
+// ===================
+// ==== RESOURCES ====
+// ===================
+
+// The player's best result for every level they've completed, keyed by
+// LevelNum. Only kept for the current session on the web.
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct Progress {
+    pub completed: BTreeMap<i32, LevelProgress>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct LevelProgress {
+    pub best_move_count: i32,
+    pub best_stars: Option<u8>,
+}
+
+impl Progress {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load() -> Self {
+        let Ok(contents) = std::fs::read_to_string(PROGRESS_PATH) else {
+            return Self::default();
+        };
+        ron::from_str(&contents).unwrap_or_else(|err| {
+            warn!("ignoring unreadable {PROGRESS_PATH}: {err}");
+            Self::default()
+        })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn load() -> Self {
+        Self::default()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                std::fs::write(PROGRESS_PATH, contents).map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            error!("could not save {PROGRESS_PATH}: {err}");
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn save(&self) {}
+}
+
+// =================
+// ==== SYSTEMS ====
+// =================
+
+fn record_progress(
+    mut progress: ResMut<Progress>,
+    mut complete_events: EventReader<LevelCompleteEvent>,
+) {
+    for event in complete_events.iter() {
+        let entry = progress
+            .completed
+            .entry(event.level_num)
+            .or_insert(LevelProgress {
+                best_move_count: event.move_count,
+                best_stars: event.stars,
+            });
+        entry.best_move_count = entry.best_move_count.min(event.move_count);
+        entry.best_stars = entry.best_stars.max(event.stars);
+        progress.save();
+    }
+}
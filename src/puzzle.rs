@@ -0,0 +1,1930 @@
+use std::{
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use crate::{
+    level::{Influence, MetaGridCoords, MetaLevel},
+    player::Direction,
+    tiles::{TileBehavior, TileDefinitions},
+};
+use bevy::{
+    math::IVec2,
+    utils::{HashMap, HashSet},
+};
+use bevy_ecs_ldtk::{
+    ldtk::{FieldValue, Level},
+    GridCoords,
+};
+use serde::{Deserialize, Serialize};
+
+// A headless copy of the puzzle rules. The ECS systems forward every move and
+// swap to a `PuzzleState` and then mirror its results onto the spawned
+// entities, so everything here can be run without a window.
+
+// anything that can be moved, by its index in the puzzle
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Piece {
+    Player(usize),
+    Box(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Motion {
+    // `distance` is in tiles. more than one when sliding across ice
+    Step { direction: Direction, distance: i32 },
+    Teleport,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MoveResult {
+    pub piece: Piece,
+    pub motion: Motion,
+    // where the piece ended up
+    pub sub_level: usize,
+    pub coords: GridCoords,
+}
+
+// how a player moves when the player in a neighboring sub-level moves, set by
+// the Response field on the Player entity
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlayerResponse {
+    #[default]
+    Same,
+    Inverted,
+    // left and right are swapped
+    MirrorX,
+    // up and down are swapped
+    MirrorY,
+    Ignore,
+}
+
+impl PlayerResponse {
+    fn from_field(value: Option<&str>) -> Self {
+        match value {
+            Some("Inverted") => Self::Inverted,
+            Some("MirrorX") => Self::MirrorX,
+            Some("MirrorY") => Self::MirrorY,
+            Some("Ignore") => Self::Ignore,
+            _ => Self::Same,
+        }
+    }
+
+    pub fn respond(self, direction: Direction) -> Option<Direction> {
+        match (self, direction) {
+            (Self::Same, _) => Some(direction),
+            (Self::Inverted | Self::MirrorX, Direction::Left) => Some(Direction::Right),
+            (Self::Inverted | Self::MirrorX, Direction::Right) => Some(Direction::Left),
+            (Self::Inverted | Self::MirrorY, Direction::Up) => Some(Direction::Down),
+            (Self::Inverted | Self::MirrorY, Direction::Down) => Some(Direction::Up),
+            (Self::MirrorX | Self::MirrorY, _) => Some(direction),
+            (Self::Ignore, _) => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Turn {
+    // a quarter turn clockwise
+    Rotate,
+    // left and right are swapped
+    Flip,
+}
+
+// Which way a sub-level has been turned. Its tiles and pieces keep their own
+// coordinates and turn along with it, so only directions and positions that
+// reach outside of the sub-level need converting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Orientation {
+    // mirrored left to right, before the quarter turns
+    pub flipped: bool,
+    // clockwise
+    pub quarter_turns: u8,
+}
+
+impl Orientation {
+    fn turned(self, turn: Turn) -> Self {
+        match turn {
+            Turn::Rotate => Self {
+                quarter_turns: (self.quarter_turns + 1) % 4,
+                ..self
+            },
+            // mirroring a turned sub-level on screen undoes its turns
+            Turn::Flip => Self {
+                flipped: !self.flipped,
+                quarter_turns: (4 - self.quarter_turns) % 4,
+            },
+        }
+    }
+
+    // a mirrored orientation is its own inverse
+    fn inverse(self) -> Self {
+        if self.flipped {
+            self
+        } else {
+            Self {
+                quarter_turns: (4 - self.quarter_turns) % 4,
+                ..self
+            }
+        }
+    }
+
+    // from a direction within the sub-level to the one it points on screen
+    pub fn to_screen(self, direction: Direction) -> Direction {
+        let direction = if self.flipped {
+            direction.mirrored()
+        } else {
+            direction
+        };
+        (0..self.quarter_turns).fold(direction, |direction, _| direction.rotated_clockwise())
+    }
+
+    pub fn to_local(self, direction: Direction) -> Direction {
+        self.inverse().to_screen(direction)
+    }
+
+    // from coordinates within a `width` by `height` sub-level to the ones they
+    // end up at in its slot
+    fn to_slot(self, coords: GridCoords, width: i32, height: i32) -> GridCoords {
+        let mut coords = if self.flipped {
+            GridCoords::new(width - 1 - coords.x, coords.y)
+        } else {
+            coords
+        };
+        let (mut width, mut height) = (width, height);
+        for _ in 0..self.quarter_turns {
+            coords = GridCoords::new(coords.y, width - 1 - coords.x);
+            (width, height) = (height, width);
+        }
+        coords
+    }
+
+    fn to_local_coords(self, coords: GridCoords, width: i32, height: i32) -> GridCoords {
+        // a sub-level on its side is as wide as it used to be high
+        let (width, height) = if self.quarter_turns % 2 == 1 {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        self.inverse().to_slot(coords, width, height)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PuzzlePlayer {
+    pub sub_level: usize,
+    pub coords: GridCoords,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PuzzleBox {
+    pub sub_level: usize,
+    pub coords: GridCoords,
+}
+
+// pressing any of its plates opens a door, even from another sub-level
+#[derive(Clone, Debug)]
+pub struct PuzzleDoor {
+    pub sub_level: usize,
+    pub coords: GridCoords,
+    plates: Vec<usize>,
+}
+
+// sends a player that ends a move on it to its partner in the same sub-level
+#[derive(Clone, Copy, Debug)]
+pub struct PuzzleTeleporter {
+    pub sub_level: usize,
+    pub coords: GridCoords,
+    partner: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PuzzlePlate {
+    pub sub_level: usize,
+    pub coords: GridCoords,
+}
+
+#[derive(Clone, Debug)]
+pub struct SubLevel {
+    pub iid: String,
+    pub width: i32,
+    pub height: i32,
+    // indexed by `y * width + x`, with `y` pointing up like `GridCoords`
+    tiles: Vec<Option<TileBehavior>>,
+}
+
+impl SubLevel {
+    pub fn tile(&self, coords: GridCoords) -> Option<TileBehavior> {
+        if coords.x < 0 || coords.y < 0 || coords.x >= self.width || coords.y >= self.height {
+            return None;
+        }
+        self.tiles[(coords.y * self.width + coords.x) as usize]
+    }
+
+    pub fn goal_coords(&self) -> impl Iterator<Item = GridCoords> + '_ {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, tile)| tile.is_some_and(|tile| tile.goal))
+            .map(|(i, _)| GridCoords::new(i as i32 % self.width, i as i32 / self.width))
+    }
+}
+
+// Everything about a meta-level that never changes while it is being played.
+#[derive(Debug)]
+struct PuzzleLayout {
+    meta_grid_width: i32,
+    meta_grid_height: i32,
+    cross_boundaries: bool,
+    influence: Influence,
+    locked: HashSet<MetaGridCoords>,
+    sliding: bool,
+    rotatable: bool,
+    // how often the primary player can be switched to another player.
+    // negative for no limit
+    primary_switches: i32,
+    // whether the meta-grid's opposite edges are next to each other
+    wraparound: bool,
+    sub_levels: Vec<SubLevel>,
+    player_iids: Vec<String>,
+    player_responses: Vec<PlayerResponse>,
+    box_iids: Vec<String>,
+    plates: Vec<PuzzlePlate>,
+    doors: Vec<PuzzleDoor>,
+    door_iids: Vec<String>,
+    teleporters: Vec<PuzzleTeleporter>,
+    // how the values that cracked floor crumbles into behave
+    crumbled_tiles: HashMap<i32, Option<TileBehavior>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PuzzleState {
+    layout: Arc<PuzzleLayout>,
+    // indexed by sub-level
+    positions: Vec<MetaGridCoords>,
+    // also indexed by sub-level
+    orientations: Vec<Orientation>,
+    players: Vec<PuzzlePlayer>,
+    boxes: Vec<PuzzleBox>,
+    // cracked floor that has turned into lava, sorted so that equal states
+    // compare and hash the same
+    crumbled: Vec<(usize, GridCoords)>,
+    primary: usize,
+    // only counted when there is a limit, so that unlimited switching doesn't
+    // tell otherwise equal states apart
+    switches_used: i32,
+}
+
+impl PartialEq for PuzzleState {
+    fn eq(&self, other: &Self) -> bool {
+        self.positions == other.positions
+            && self.orientations == other.orientations
+            && self.players == other.players
+            && self.boxes == other.boxes
+            && self.crumbled == other.crumbled
+            && self.primary == other.primary
+            && self.switches_used == other.switches_used
+    }
+}
+
+impl Eq for PuzzleState {}
+
+impl Hash for PuzzleState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.positions.hash(state);
+        self.orientations.hash(state);
+        self.players.hash(state);
+        self.boxes.hash(state);
+        self.crumbled.hash(state);
+        self.primary.hash(state);
+        self.switches_used.hash(state);
+    }
+}
+
+// A sub-level as plain data, laid out the way LDtk stores it: rows of
+// `int_grid_csv` and the `grid` of every entity count from the top.
+#[derive(Clone, Debug, Default)]
+pub struct SubLevelData {
+    pub iid: String,
+    pub width: i32,
+    pub height: i32,
+    pub int_grid_csv: Vec<i32>,
+    pub entities: Vec<EntityData>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EntityData {
+    pub iid: String,
+    pub grid: IVec2,
+    pub kind: EntityKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum EntityKind {
+    Player {
+        primary: bool,
+        response: PlayerResponse,
+    },
+    Box,
+    Plate,
+    // by the iids of the plates that open it
+    Door {
+        plates: Vec<String>,
+    },
+    Teleporter {
+        partner: Option<String>,
+    },
+}
+
+impl SubLevelData {
+    // entities the puzzle doesn't care about are left out
+    pub fn from_ldtk_level(level: &Level) -> Self {
+        let layers = level
+            .layer_instances
+            .as_ref()
+            .expect("level layers are embedded in the LDtk project");
+        let tile_data = layers
+            .iter()
+            .find(|layer| layer.identifier == "TileData")
+            .expect("TileData layer exists");
+        let entities = layers
+            .iter()
+            .find(|layer| layer.identifier == "Entities")
+            .expect("Entities layer exists");
+        let entities = entities
+            .entity_instances
+            .iter()
+            .filter_map(|instance| {
+                let kind = match instance.identifier.as_str() {
+                    "Player" => EntityKind::Player {
+                        primary: instance.field_instances.iter().any(|field| {
+                            field.identifier == "Primary"
+                                && matches!(field.value, FieldValue::Bool(true))
+                        }),
+                        response: PlayerResponse::from_field(
+                            instance
+                                .field_instances
+                                .iter()
+                                .find_map(|field| match &field.value {
+                                    FieldValue::Enum(value) if field.identifier == "Response" => {
+                                        value.as_deref()
+                                    }
+                                    _ => None,
+                                }),
+                        ),
+                    },
+                    "Box" => EntityKind::Box,
+                    "Plate" => EntityKind::Plate,
+                    "Door" => EntityKind::Door {
+                        plates: instance
+                            .field_instances
+                            .iter()
+                            .filter_map(|field| match &field.value {
+                                FieldValue::EntityRefs(refs) if field.identifier == "Plates" => {
+                                    Some(refs)
+                                }
+                                _ => None,
+                            })
+                            .flatten()
+                            .flatten()
+                            .map(|entity_ref| entity_ref.entity_iid.clone())
+                            .collect(),
+                    },
+                    "Teleporter" => EntityKind::Teleporter {
+                        partner: instance.field_instances.iter().find_map(|field| {
+                            match &field.value {
+                                FieldValue::EntityRef(Some(entity_ref))
+                                    if field.identifier == "Partner" =>
+                                {
+                                    Some(entity_ref.entity_iid.clone())
+                                }
+                                _ => None,
+                            }
+                        }),
+                    },
+                    _ => return None,
+                };
+                Some(EntityData {
+                    iid: instance.iid.clone(),
+                    grid: instance.grid,
+                    kind,
+                })
+            })
+            .collect();
+        Self {
+            iid: level.iid.clone(),
+            width: tile_data.c_wid,
+            height: tile_data.c_hei,
+            int_grid_csv: tile_data.int_grid_csv.clone(),
+            entities,
+        }
+    }
+}
+
+impl PuzzleState {
+    pub fn from_ldtk_levels<'a>(
+        meta_level: &MetaLevel,
+        levels: impl IntoIterator<Item = &'a Level>,
+        tile_definitions: &TileDefinitions,
+    ) -> Self {
+        let sub_levels = levels
+            .into_iter()
+            .filter(|level| {
+                meta_level
+                    .initial_placement
+                    .values()
+                    .any(|iid| *iid == level.iid)
+            })
+            .map(SubLevelData::from_ldtk_level)
+            .collect::<Vec<_>>();
+        Self::from_sub_levels(meta_level, &sub_levels, tile_definitions)
+    }
+
+    // `sub_levels` can include levels that aren't part of the meta-level
+    pub fn from_sub_levels(
+        meta_level: &MetaLevel,
+        sub_levels: &[SubLevelData],
+        tile_definitions: &TileDefinitions,
+    ) -> Self {
+        let levels_by_iid: HashMap<&str, &SubLevelData> = sub_levels
+            .iter()
+            .map(|level| (level.iid.as_str(), level))
+            .collect();
+
+        // sort the placement so that sub-level and player indices are stable
+        let mut placement = meta_level.initial_placement.iter().collect::<Vec<_>>();
+        placement.sort_by_key(|(pos, _)| (pos.row, pos.col));
+
+        let mut sub_levels = vec![];
+        let mut positions = vec![];
+        let mut players = vec![];
+        let mut player_iids = vec![];
+        let mut player_responses = vec![];
+        let mut boxes = vec![];
+        let mut box_iids = vec![];
+        let mut plates = vec![];
+        let mut plate_iids = vec![];
+        // doors are linked up once every plate is known
+        let mut door_refs = vec![];
+        let mut door_iids = vec![];
+        let mut teleporters = vec![];
+        let mut primary = None;
+        for (&pos, iid) in placement {
+            let level = levels_by_iid
+                .get(iid.as_str())
+                .expect("level iid exists in LDtk project");
+            let (width, height) = (level.width, level.height);
+            // LDtk stores rows top to bottom, but GridCoords count from the bottom.
+            // values without a definition are left empty, the same as 0
+            let tiles = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| level.int_grid_csv[((height - 1 - y) * width + x) as usize])
+                .map(|value| tile_definitions.behavior(value))
+                .collect();
+
+            let sub_level = sub_levels.len();
+            // partners are looked up by iid within the same sub-level
+            let teleporter_iids = level
+                .entities
+                .iter()
+                .filter(|entity| matches!(entity.kind, EntityKind::Teleporter { .. }))
+                .map(|entity| entity.iid.as_str())
+                .collect::<Vec<_>>();
+            let first_teleporter = teleporters.len();
+            for entity in &level.entities {
+                let coords = GridCoords::new(entity.grid.x, height - 1 - entity.grid.y);
+                match &entity.kind {
+                    EntityKind::Player {
+                        primary: is_primary,
+                        response,
+                    } => {
+                        if *is_primary {
+                            primary = Some(players.len());
+                        }
+                        players.push(PuzzlePlayer { sub_level, coords });
+                        player_iids.push(entity.iid.clone());
+                        player_responses.push(*response);
+                    }
+                    EntityKind::Box => {
+                        boxes.push(PuzzleBox { sub_level, coords });
+                        box_iids.push(entity.iid.clone());
+                    }
+                    EntityKind::Plate => {
+                        plates.push(PuzzlePlate { sub_level, coords });
+                        plate_iids.push(entity.iid.as_str());
+                    }
+                    EntityKind::Door { plates } => {
+                        door_refs.push((sub_level, coords, plates));
+                        door_iids.push(entity.iid.clone());
+                    }
+                    EntityKind::Teleporter { partner } => {
+                        let partner = partner
+                            .as_ref()
+                            .and_then(|iid| {
+                                teleporter_iids
+                                    .iter()
+                                    .position(|&other| other == iid.as_str())
+                            })
+                            .map(|index| first_teleporter + index);
+                        teleporters.push(PuzzleTeleporter {
+                            sub_level,
+                            coords,
+                            partner,
+                        });
+                    }
+                }
+            }
+
+            sub_levels.push(SubLevel {
+                iid: iid.clone(),
+                width,
+                height,
+                tiles,
+            });
+            positions.push(pos);
+        }
+
+        // plates outside of this meta-level are ignored
+        let doors = door_refs
+            .into_iter()
+            .map(|(sub_level, coords, refs)| PuzzleDoor {
+                sub_level,
+                coords,
+                plates: refs
+                    .iter()
+                    .filter_map(|iid| plate_iids.iter().position(|&other| other == iid.as_str()))
+                    .collect(),
+            })
+            .collect();
+
+        let crumbled_tiles = tile_definitions
+            .tiles
+            .values()
+            .filter_map(|definition| definition.behavior.crumbles_into)
+            .map(|value| (value, tile_definitions.behavior(value)))
+            .collect();
+
+        Self {
+            layout: Arc::new(PuzzleLayout {
+                meta_grid_width: meta_level.meta_grid_width,
+                meta_grid_height: meta_level.meta_grid_height,
+                cross_boundaries: meta_level.cross_boundaries,
+                influence: meta_level.influence,
+                locked: meta_level.locked.clone(),
+                sliding: meta_level.sliding,
+                rotatable: meta_level.rotatable,
+                primary_switches: meta_level.primary_switches,
+                wraparound: meta_level.wraparound,
+                sub_levels,
+                player_iids,
+                player_responses,
+                box_iids,
+                plates,
+                doors,
+                door_iids,
+                teleporters,
+                crumbled_tiles,
+            }),
+            orientations: vec![Orientation::default(); positions.len()],
+            positions,
+            players,
+            boxes,
+            crumbled: vec![],
+            primary: primary.expect("meta-level has a primary player"),
+            switches_used: 0,
+        }
+    }
+
+    pub fn meta_grid_width(&self) -> i32 {
+        self.layout.meta_grid_width
+    }
+
+    pub fn meta_grid_height(&self) -> i32 {
+        self.layout.meta_grid_height
+    }
+
+    pub fn sub_levels(&self) -> &[SubLevel] {
+        &self.layout.sub_levels
+    }
+
+    pub fn players(&self) -> &[PuzzlePlayer] {
+        &self.players
+    }
+
+    pub fn player_iid(&self, player: usize) -> &str {
+        &self.layout.player_iids[player]
+    }
+
+    pub fn player_response(&self, player: usize) -> PlayerResponse {
+        self.layout.player_responses[player]
+    }
+
+    pub fn player_index(&self, iid: &str) -> Option<usize> {
+        self.layout
+            .player_iids
+            .iter()
+            .position(|other| other == iid)
+    }
+
+    pub fn boxes(&self) -> &[PuzzleBox] {
+        &self.boxes
+    }
+
+    pub fn box_index(&self, iid: &str) -> Option<usize> {
+        self.layout.box_iids.iter().position(|other| other == iid)
+    }
+
+    // the tile as it is right now, once cracked floor may have crumbled
+    pub fn tile(&self, sub_level: usize, coords: GridCoords) -> Option<TileBehavior> {
+        let tile = self.layout.sub_levels[sub_level].tile(coords);
+        if !self.is_crumbled(sub_level, coords) {
+            return tile;
+        }
+        tile.and_then(|tile| tile.crumbles_into)
+            .and_then(|value| self.layout.crumbled_tiles[&value])
+    }
+
+    pub fn is_crumbled(&self, sub_level: usize, coords: GridCoords) -> bool {
+        self.crumbled.contains(&(sub_level, coords))
+    }
+
+    pub fn doors(&self) -> &[PuzzleDoor] {
+        &self.layout.doors
+    }
+
+    pub fn door_index(&self, iid: &str) -> Option<usize> {
+        self.layout.door_iids.iter().position(|other| other == iid)
+    }
+
+    pub fn is_door_open(&self, door: usize) -> bool {
+        self.layout.doors[door].plates.iter().any(|&plate| {
+            let PuzzlePlate { sub_level, coords } = self.layout.plates[plate];
+            self.player_at(sub_level, coords).is_some()
+        })
+    }
+
+    pub fn piece_coords(&self, piece: Piece) -> GridCoords {
+        match piece {
+            Piece::Player(player) => self.players[player].coords,
+            Piece::Box(index) => self.boxes[index].coords,
+        }
+    }
+
+    pub fn piece_sub_level(&self, piece: Piece) -> usize {
+        match piece {
+            Piece::Player(player) => self.players[player].sub_level,
+            Piece::Box(index) => self.boxes[index].sub_level,
+        }
+    }
+
+    pub fn primary(&self) -> usize {
+        self.primary
+    }
+
+    pub fn sub_level_index(&self, iid: &str) -> Option<usize> {
+        self.layout
+            .sub_levels
+            .iter()
+            .position(|sub_level| sub_level.iid == iid)
+    }
+
+    pub fn position(&self, sub_level: usize) -> MetaGridCoords {
+        self.positions[sub_level]
+    }
+
+    pub fn orientation(&self, sub_level: usize) -> Orientation {
+        self.orientations[sub_level]
+    }
+
+    pub fn sub_level_at(&self, pos: MetaGridCoords) -> Option<usize> {
+        self.positions.iter().position(|&other| other == pos)
+    }
+
+    pub fn primary_position(&self) -> MetaGridCoords {
+        self.position(self.players[self.primary].sub_level)
+    }
+
+    // None if there is no limit
+    pub fn switches_left(&self) -> Option<i32> {
+        (self.layout.primary_switches >= 0)
+            .then(|| self.layout.primary_switches - self.switches_used)
+    }
+
+    pub fn can_switch_primary(&self, player: usize) -> bool {
+        player < self.players.len()
+            && player != self.primary
+            && self.layout.primary_switches != 0
+            && self.switches_left().is_none_or(|left| left > 0)
+    }
+
+    // the other players are then moved by the new primary player instead
+    pub fn apply_switch_primary(&mut self, player: usize) -> bool {
+        if !self.can_switch_primary(player) {
+            return false;
+        }
+        self.primary = player;
+        if self.switches_left().is_some() {
+            self.switches_used += 1;
+        }
+        true
+    }
+
+    // Moves the primary player and, if it moved, every player in a sub-level
+    // that the meta-level's `Influence` connects to it, and then runs the
+    // conveyors and teleporters. This is one full turn.
+    pub fn apply_move(&mut self, direction: Direction) -> Option<Vec<MoveResult>> {
+        let mut results = self.move_player(self.primary, direction)?;
+        results.extend(self.move_neighboring_players(self.primary_position(), direction));
+        results.extend(self.move_players_on_conveyors());
+        let moved = results
+            .iter()
+            .filter_map(|result| match result.piece {
+                Piece::Player(player) => Some(player),
+                Piece::Box(_) => None,
+            })
+            .collect::<Vec<_>>();
+        results.extend(self.move_players_on_teleporters(&moved));
+        Some(results)
+    }
+
+    pub fn is_locked(&self, pos: MetaGridCoords) -> bool {
+        self.layout.locked.contains(&pos)
+    }
+
+    fn wrap(&self) -> Option<(i32, i32)> {
+        self.layout
+            .wraparound
+            .then_some((self.layout.meta_grid_height, self.layout.meta_grid_width))
+    }
+
+    // Either slot can be empty, but not both. In sliding mode exactly one of
+    // them has to be, and they have to be next to each other.
+    pub fn can_swap(&self, from_pos: MetaGridCoords, to_pos: MetaGridCoords) -> bool {
+        if from_pos == to_pos || self.is_locked(from_pos) || self.is_locked(to_pos) {
+            return false;
+        }
+        let from = self.sub_level_at(from_pos);
+        let to = self.sub_level_at(to_pos);
+        if self.layout.sliding {
+            from.is_some() != to.is_some() && from_pos.is_neighbor(to_pos, self.wrap())
+        } else {
+            from.is_some() || to.is_some()
+        }
+    }
+
+    pub fn apply_swap(&mut self, from_pos: MetaGridCoords, to_pos: MetaGridCoords) -> bool {
+        if !self.can_swap(from_pos, to_pos) {
+            return false;
+        }
+        let from = self.sub_level_at(from_pos);
+        let to = self.sub_level_at(to_pos);
+        if let Some(from) = from {
+            self.positions[from] = to_pos;
+        }
+        if let Some(to) = to {
+            self.positions[to] = from_pos;
+        }
+        true
+    }
+
+    pub fn is_rotatable(&self) -> bool {
+        self.layout.rotatable
+    }
+
+    // Sub-levels can only be turned on their side if they are square, or they
+    // wouldn't fit their slot anymore.
+    pub fn can_turn(&self, pos: MetaGridCoords, turn: Turn) -> bool {
+        let Some(sub_level) = self.sub_level_at(pos) else {
+            return false;
+        };
+        let level = &self.layout.sub_levels[sub_level];
+        self.layout.rotatable
+            && !self.is_locked(pos)
+            && (turn == Turn::Flip || level.width == level.height)
+    }
+
+    // everything inside the sub-level turns along with it, so nothing else
+    // changes
+    pub fn apply_turn(&mut self, pos: MetaGridCoords, turn: Turn) -> bool {
+        if !self.can_turn(pos, turn) {
+            return false;
+        }
+        let sub_level = self.sub_level_at(pos).expect("turned slot has a sub-level");
+        self.orientations[sub_level] = self.orientations[sub_level].turned(turn);
+        true
+    }
+
+    // `direction` is on screen, whichever way the player's sub-level is turned
+    pub fn move_player(&mut self, player: usize, direction: Direction) -> Option<Vec<MoveResult>> {
+        self.player_movement_logic(player, self.local_direction(player, direction))
+    }
+
+    pub fn move_neighboring_players(
+        &mut self,
+        grid_coords: MetaGridCoords,
+        direction: Direction,
+    ) -> Vec<MoveResult> {
+        let mut movers = (0..self.players.len())
+            .filter(|&player| {
+                let pos = self.positions[self.players[player].sub_level];
+                self.layout
+                    .influence
+                    .connects(pos, grid_coords, self.wrap())
+            })
+            .filter_map(|player| Some((player, self.player_response(player).respond(direction)?)))
+            .collect::<Vec<_>>();
+        // players block each other, so move whoever is furthest ahead first and
+        // let the others follow. ties are broken by player index.
+        movers.sort_by_key(|&(player, direction)| {
+            std::cmp::Reverse(self.progress_along(player, direction))
+        });
+        let mut results = vec![];
+        for (player, direction) in movers {
+            if let Some(moved) = self.move_player(player, direction) {
+                results.extend(moved);
+            }
+        }
+        results
+    }
+
+    // Moves every player standing on a conveyor one tile along it, as if they
+    // had walked there themselves.
+    pub fn move_players_on_conveyors(&mut self) -> Vec<MoveResult> {
+        let mut movers = (0..self.players.len())
+            .filter_map(|player| {
+                let PuzzlePlayer { sub_level, coords } = self.players[player];
+                let direction = self.tile(sub_level, coords)?.conveyor?;
+                Some((player, self.orientations[sub_level].to_screen(direction)))
+            })
+            .collect::<Vec<_>>();
+        // same as neighbors, a player further along a belt goes first
+        movers.sort_by_key(|&(player, direction)| {
+            std::cmp::Reverse(self.progress_along(player, direction))
+        });
+        let mut results = vec![];
+        for (player, direction) in movers {
+            if let Some(moved) = self.move_player(player, direction) {
+                results.extend(moved);
+            }
+        }
+        results
+    }
+
+    // Sends every player in `moved` that ended the turn on a teleporter to its
+    // partner, unless someone is already there. Players that didn't move this
+    // turn stay put, so arriving doesn't send them straight back next turn.
+    pub fn move_players_on_teleporters(&mut self, moved: &[usize]) -> Vec<MoveResult> {
+        let mut results = vec![];
+        for player in (0..self.players.len()).filter(|player| moved.contains(player)) {
+            let PuzzlePlayer { sub_level, coords } = self.players[player];
+            let Some(partner) = self
+                .layout
+                .teleporters
+                .iter()
+                .find(|teleporter| teleporter.sub_level == sub_level && teleporter.coords == coords)
+                .and_then(|teleporter| teleporter.partner)
+            else {
+                continue;
+            };
+            let partner_coords = self.layout.teleporters[partner].coords;
+            if self.is_occupied(sub_level, partner_coords) {
+                continue;
+            }
+            self.players[player].coords = partner_coords;
+            results.push(MoveResult {
+                piece: Piece::Player(player),
+                motion: Motion::Teleport,
+                sub_level,
+                coords: partner_coords,
+            });
+        }
+        results
+    }
+
+    pub fn is_won(&self) -> bool {
+        self.layout
+            .sub_levels
+            .iter()
+            .enumerate()
+            .all(|(sub_level, level)| {
+                level
+                    .goal_coords()
+                    .all(|goal_coords| self.is_occupied(sub_level, goal_coords))
+            })
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.players
+            .iter()
+            .any(|player| self.is_deadly(player.sub_level, player.coords))
+    }
+
+    pub fn is_deadly(&self, sub_level: usize, coords: GridCoords) -> bool {
+        self.tile(sub_level, coords).is_some_and(|tile| tile.deadly)
+    }
+
+    // a box covers a goal just as well as a player does
+    fn is_occupied(&self, sub_level: usize, coords: GridCoords) -> bool {
+        self.player_at(sub_level, coords).is_some() || self.box_at(sub_level, coords).is_some()
+    }
+
+    fn local_direction(&self, player: usize, direction: Direction) -> Direction {
+        self.orientations[self.players[player].sub_level].to_local(direction)
+    }
+
+    // how far a player is in the given direction on screen, across the whole
+    // meta-grid
+    fn progress_along(&self, player: usize, direction: Direction) -> i32 {
+        let PuzzlePlayer { sub_level, coords } = self.players[player];
+        let level = &self.layout.sub_levels[sub_level];
+        let pos = self.positions[sub_level];
+        let coords = self.orientations[sub_level].to_slot(coords, level.width, level.height);
+        let x = pos.col * level.width + coords.x;
+        // meta-grid rows count down, but GridCoords count up
+        let y = -pos.row * level.height + coords.y;
+        match direction {
+            Direction::Up => y,
+            Direction::Down => -y,
+            Direction::Left => -x,
+            Direction::Right => x,
+        }
+    }
+
+    fn player_at(&self, sub_level: usize, coords: GridCoords) -> Option<usize> {
+        self.players
+            .iter()
+            .position(|other| other.sub_level == sub_level && other.coords == coords)
+    }
+
+    fn box_at(&self, sub_level: usize, coords: GridCoords) -> Option<usize> {
+        self.boxes
+            .iter()
+            .position(|other| other.sub_level == sub_level && other.coords == coords)
+    }
+
+    // one-way tiles can only be passed through in their own direction, both
+    // when entering and when leaving them
+    fn allows_direction(&self, sub_level: usize, coords: GridCoords, direction: Direction) -> bool {
+        match self.tile(sub_level, coords).and_then(|tile| tile.one_way) {
+            Some(allowed) => allowed == direction,
+            None => true,
+        }
+    }
+
+    fn is_walkable(&self, sub_level: usize, coords: GridCoords) -> bool {
+        let closed_door = self.layout.doors.iter().enumerate().any(|(door, other)| {
+            other.sub_level == sub_level && other.coords == coords && !self.is_door_open(door)
+        });
+        !closed_door && self.tile(sub_level, coords).is_some_and(|tile| !tile.solid)
+    }
+
+    // Where a player stepping off `coords` ends up, and which way it is facing
+    // there. Boundary tiles are solid, unless the meta-level lets players cross
+    // them into the neighboring sub-level, which may be turned differently.
+    fn step(
+        &self,
+        sub_level: usize,
+        coords: GridCoords,
+        direction: Direction,
+    ) -> Option<(usize, GridCoords, Direction)> {
+        let new_coords = coords + direction.unit_grid_coords();
+        let level = &self.layout.sub_levels[sub_level];
+        if !self.layout.cross_boundaries
+            || !level.tile(new_coords).is_some_and(|tile| tile.boundary)
+        {
+            return Some((sub_level, new_coords, direction));
+        }
+        // only the edges lead anywhere, not the corners
+        let on_edge = match direction {
+            Direction::Up => new_coords.y == level.height - 1,
+            Direction::Down => new_coords.y == 0,
+            Direction::Left => new_coords.x == 0,
+            Direction::Right => new_coords.x == level.width - 1,
+        };
+        let off_corner = match direction {
+            Direction::Up | Direction::Down => (1..level.width - 1).contains(&new_coords.x),
+            Direction::Left | Direction::Right => (1..level.height - 1).contains(&new_coords.y),
+        };
+        if !on_edge || !off_corner {
+            return None;
+        }
+        // the rest happens in slot coordinates, the way it looks on screen
+        let orientation = self.orientations[sub_level];
+        let direction = orientation.to_screen(direction);
+        let new_coords = orientation.to_slot(new_coords, level.width, level.height);
+        let pos = self.positions[sub_level];
+        // meta-grid rows count down, but GridCoords count up
+        let neighbor_pos = match direction {
+            Direction::Up => MetaGridCoords::new(pos.row - 1, pos.col),
+            Direction::Down => MetaGridCoords::new(pos.row + 1, pos.col),
+            Direction::Left => MetaGridCoords::new(pos.row, pos.col - 1),
+            Direction::Right => MetaGridCoords::new(pos.row, pos.col + 1),
+        }
+        .wrapped(self.wrap());
+        // a sub-level isn't its own neighbor, even on a wrapping meta-grid
+        // that is only one slot wide
+        let neighbor = self
+            .sub_level_at(neighbor_pos)
+            .filter(|&neighbor| neighbor != sub_level)?;
+        let target = &self.layout.sub_levels[neighbor];
+        // step over the boundary on the neighbor's opposite edge too
+        let target_coords = match direction {
+            Direction::Up => GridCoords::new(new_coords.x, 1),
+            Direction::Down => GridCoords::new(new_coords.x, target.height - 2),
+            Direction::Left => GridCoords::new(target.width - 2, new_coords.y),
+            Direction::Right => GridCoords::new(1, new_coords.y),
+        };
+        let target_orientation = self.orientations[neighbor];
+        Some((
+            neighbor,
+            target_orientation.to_local_coords(target_coords, target.width, target.height),
+            target_orientation.to_local(direction),
+        ))
+    }
+
+    // cracked floor turns into lava once a player steps off it
+    fn crumble(&mut self, sub_level: usize, coords: GridCoords) {
+        if self
+            .tile(sub_level, coords)
+            .and_then(|tile| tile.crumbles_into)
+            .is_none()
+        {
+            return;
+        }
+        let key = |&(sub_level, coords): &(usize, GridCoords)| (sub_level, coords.x, coords.y);
+        if let Err(index) = self
+            .crumbled
+            .binary_search_by_key(&key(&(sub_level, coords)), key)
+        {
+            self.crumbled.insert(index, (sub_level, coords));
+        }
+    }
+
+    // `direction` is within the player's own sub-level
+    fn player_movement_logic(
+        &mut self,
+        player: usize,
+        direction: Direction,
+    ) -> Option<Vec<MoveResult>> {
+        let from = self.players[player];
+        let PuzzlePlayer { sub_level, coords } = from;
+        if !self.allows_direction(sub_level, coords, direction) {
+            return None;
+        }
+        let (sub_level, new_coords, mut direction) = self.step(sub_level, coords, direction)?;
+        // players can't share a tile
+        if !self.is_walkable(sub_level, new_coords)
+            || !self.allows_direction(sub_level, new_coords, direction)
+            || self.player_at(sub_level, new_coords).is_some()
+        {
+            return None;
+        }
+        let mut results = vec![];
+        // push a box out of the way, Sokoban style: only one at a time, and
+        // never into a wall, another box or a player. one-way tiles apply to
+        // boxes too.
+        if let Some(pushed) = self.box_at(sub_level, new_coords) {
+            let box_coords = new_coords + direction.unit_grid_coords();
+            if !self.is_walkable(sub_level, box_coords)
+                || !self.allows_direction(sub_level, box_coords, direction)
+                || self.is_occupied(sub_level, box_coords)
+            {
+                return None;
+            }
+            self.boxes[pushed].coords = box_coords;
+            results.push(MoveResult {
+                piece: Piece::Box(pushed),
+                motion: Motion::Step {
+                    direction,
+                    distance: 1,
+                },
+                sub_level,
+                coords: box_coords,
+            });
+        }
+        self.players[player] = PuzzlePlayer {
+            sub_level,
+            coords: new_coords,
+        };
+        self.crumble(from.sub_level, from.coords);
+        // keep sliding across ice until something gets in the way or the
+        // player reaches a tile that isn't ice. on a wrapping meta-grid the ice
+        // can go all the way around, so also stop before covering a tile twice
+        let mut distance = 1;
+        let mut visited = vec![(sub_level, new_coords)];
+        loop {
+            let PuzzlePlayer { sub_level, coords } = self.players[player];
+            if !self
+                .tile(sub_level, coords)
+                .is_some_and(|tile| tile.slippery)
+            {
+                break;
+            }
+            let Some((sub_level, coords, next_direction)) = self.step(sub_level, coords, direction)
+            else {
+                break;
+            };
+            if !self.is_walkable(sub_level, coords)
+                || !self.allows_direction(sub_level, coords, next_direction)
+                || self.is_occupied(sub_level, coords)
+                || visited.contains(&(sub_level, coords))
+            {
+                break;
+            }
+            visited.push((sub_level, coords));
+            self.players[player] = PuzzlePlayer { sub_level, coords };
+            direction = next_direction;
+            distance += 1;
+        }
+        results.push(MoveResult {
+            piece: Piece::Player(player),
+            motion: Motion::Step {
+                direction,
+                distance,
+            },
+            sub_level: self.players[player].sub_level,
+            coords: self.players[player].coords,
+        });
+        Some(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IntGrid values from beside_yourself.tiles.ron
+    const F: i32 = 1;
+    const G: i32 = 2;
+    const W: i32 = 3;
+    const B: i32 = 4;
+    const L: i32 = 5;
+    const I: i32 = 6;
+    // ConveyorRight
+    const C: i32 = 10;
+    // OneWayRight
+    const O: i32 = 14;
+    // CrackedFloor
+    const K: i32 = 15;
+
+    const ROOM: [&[i32]; 5] = [
+        &[B, B, B, B, B],
+        &[B, F, F, F, B],
+        &[B, F, F, F, B],
+        &[B, F, F, F, B],
+        &[B, B, B, B, B],
+    ];
+
+    fn tile_definitions() -> TileDefinitions {
+        TileDefinitions::from_ron(include_str!("../assets/levels/beside_yourself.tiles.ron"))
+            .expect("tile definitions are valid")
+    }
+
+    // `rows` go top to bottom, the same as in LDtk
+    fn sub_level(iid: &str, rows: &[&[i32]], entities: Vec<EntityData>) -> SubLevelData {
+        SubLevelData {
+            iid: iid.to_owned(),
+            width: rows[0].len() as i32,
+            height: rows.len() as i32,
+            int_grid_csv: rows.concat(),
+            entities,
+        }
+    }
+
+    fn entity(iid: &str, x: i32, y: i32, kind: EntityKind) -> EntityData {
+        EntityData {
+            iid: iid.to_owned(),
+            grid: IVec2::new(x, y),
+            kind,
+        }
+    }
+
+    fn player(x: i32, y: i32) -> EntityData {
+        let kind = EntityKind::Player {
+            primary: false,
+            response: PlayerResponse::Same,
+        };
+        entity(&format!("player {x},{y}"), x, y, kind)
+    }
+
+    fn primary(x: i32, y: i32) -> EntityData {
+        let kind = EntityKind::Player {
+            primary: true,
+            response: PlayerResponse::Same,
+        };
+        entity(&format!("primary {x},{y}"), x, y, kind)
+    }
+
+    fn boxed(x: i32, y: i32) -> EntityData {
+        entity(&format!("box {x},{y}"), x, y, EntityKind::Box)
+    }
+
+    // `placement` is the row and column of each sub-level's iid
+    fn meta_level(placement: &[(i32, i32, &str)]) -> MetaLevel {
+        MetaLevel {
+            meta_grid_width: placement.iter().map(|&(_, col, _)| col + 1).max().unwrap(),
+            meta_grid_height: placement.iter().map(|&(row, ..)| row + 1).max().unwrap(),
+            initial_placement: placement
+                .iter()
+                .map(|&(row, col, iid)| (MetaGridCoords::new(row, col), iid.to_owned()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn puzzle(meta_level: &MetaLevel, sub_levels: &[SubLevelData]) -> PuzzleState {
+        PuzzleState::from_sub_levels(meta_level, sub_levels, &tile_definitions())
+    }
+
+    #[test]
+    fn apply_move_moves_neighboring_players_too() {
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b"), (1, 1, "c")]),
+            &[
+                sub_level("a", &ROOM, vec![primary(1, 1)]),
+                sub_level("b", &ROOM, vec![player(1, 1)]),
+                // only diagonal from the primary player
+                sub_level("c", &ROOM, vec![player(1, 1)]),
+            ],
+        );
+        let results = puzzle
+            .apply_move(Direction::Right)
+            .expect("primary can move");
+        assert_eq!(results.len(), 2);
+        assert_eq!(puzzle.players()[0].coords, GridCoords::new(2, 3));
+        assert_eq!(puzzle.players()[1].coords, GridCoords::new(2, 3));
+        assert_eq!(puzzle.players()[2].coords, GridCoords::new(1, 3));
+    }
+
+    #[test]
+    fn apply_move_fails_if_the_primary_player_is_blocked() {
+        let room: [&[i32]; 5] = [
+            &[B, B, B, B, B],
+            &[B, F, W, F, B],
+            &[B, F, F, F, B],
+            &[B, F, F, F, B],
+            &[B, B, B, B, B],
+        ];
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b")]),
+            &[
+                sub_level("a", &room, vec![primary(1, 1)]),
+                sub_level("b", &ROOM, vec![player(1, 1)]),
+            ],
+        );
+        let before = puzzle.clone();
+        assert!(puzzle.apply_move(Direction::Right).is_none());
+        assert_eq!(puzzle, before);
+        // the neighbor isn't blocked, but only moves along with the primary player
+        assert!(puzzle.apply_move(Direction::Down).is_some());
+        assert_eq!(puzzle.players()[1].coords, GridCoords::new(1, 2));
+    }
+
+    #[test]
+    fn apply_swap_moves_sub_levels_and_keeps_their_players() {
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b"), (0, 2, "c")]),
+            &[
+                sub_level("a", &ROOM, vec![primary(1, 1)]),
+                sub_level("b", &ROOM, vec![player(1, 1)]),
+                sub_level("c", &ROOM, vec![player(3, 3)]),
+            ],
+        );
+        assert!(puzzle.apply_swap(MetaGridCoords::new(0, 0), MetaGridCoords::new(0, 2)));
+        assert_eq!(puzzle.position(0), MetaGridCoords::new(0, 2));
+        assert_eq!(puzzle.position(2), MetaGridCoords::new(0, 0));
+        assert_eq!(puzzle.primary_position(), MetaGridCoords::new(0, 2));
+        assert!(!puzzle.apply_swap(MetaGridCoords::new(0, 1), MetaGridCoords::new(0, 1)));
+        // both slots are outside of the meta-grid, so both are empty
+        assert!(!puzzle.apply_swap(MetaGridCoords::new(1, 0), MetaGridCoords::new(1, 1)));
+    }
+
+    #[test]
+    fn is_won_once_every_goal_is_covered() {
+        let room: [&[i32]; 5] = [
+            &[B, B, B, B, B],
+            &[B, F, F, G, B],
+            &[B, F, F, F, B],
+            &[B, F, F, F, B],
+            &[B, B, B, B, B],
+        ];
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b")]),
+            &[
+                sub_level("a", &room, vec![primary(1, 1)]),
+                sub_level("b", &room, vec![player(2, 1)]),
+            ],
+        );
+        assert!(!puzzle.is_won());
+        puzzle.apply_move(Direction::Right);
+        // the neighbor got there first
+        assert!(!puzzle.is_won());
+        puzzle.apply_move(Direction::Right);
+        assert!(puzzle.is_won());
+    }
+
+    #[test]
+    fn is_lost_once_any_player_stands_on_lava() {
+        let room: [&[i32]; 5] = [
+            &[B, B, B, B, B],
+            &[B, F, F, F, B],
+            &[B, F, F, F, B],
+            &[B, F, F, L, B],
+            &[B, B, B, B, B],
+        ];
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b")]),
+            &[
+                sub_level("a", &ROOM, vec![primary(1, 3)]),
+                sub_level("b", &room, vec![player(2, 3)]),
+            ],
+        );
+        assert!(!puzzle.is_lost());
+        puzzle.apply_move(Direction::Up);
+        assert!(!puzzle.is_lost());
+        puzzle.apply_move(Direction::Down);
+        puzzle.apply_move(Direction::Right);
+        assert!(puzzle.is_lost());
+    }
+
+    #[test]
+    fn boxes_are_pushed_unless_a_wall_or_another_box_is_behind_them() {
+        let room: [&[i32]; 5] = [
+            &[B, B, B, B, B],
+            &[B, F, F, W, B],
+            &[B, F, F, F, B],
+            &[B, F, F, F, B],
+            &[B, B, B, B, B],
+        ];
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a")]),
+            &[sub_level(
+                "a",
+                &room,
+                vec![
+                    primary(1, 1),
+                    boxed(2, 1),
+                    boxed(2, 2),
+                    boxed(2, 3),
+                    boxed(3, 3),
+                ],
+            )],
+        );
+        let before = puzzle.clone();
+        assert!(puzzle.apply_move(Direction::Right).is_none());
+        assert_eq!(puzzle, before);
+        puzzle.apply_move(Direction::Down);
+        puzzle.apply_move(Direction::Down);
+        assert!(puzzle.apply_move(Direction::Right).is_none());
+        puzzle.apply_move(Direction::Up);
+        let results = puzzle.apply_move(Direction::Right).expect("box can move");
+        let pushed = puzzle.box_index("box 2,2").unwrap();
+        assert_eq!(
+            results[0],
+            MoveResult {
+                piece: Piece::Box(pushed),
+                motion: Motion::Step {
+                    direction: Direction::Right,
+                    distance: 1,
+                },
+                sub_level: 0,
+                coords: GridCoords::new(3, 2),
+            }
+        );
+        assert_eq!(puzzle.players()[0].coords, GridCoords::new(2, 2));
+        assert_eq!(puzzle.boxes()[pushed].coords, GridCoords::new(3, 2));
+    }
+
+    #[test]
+    fn players_cross_boundaries_into_the_neighboring_sub_level() {
+        let placement = [(0, 0, "a"), (0, 1, "b")];
+        let sub_levels = [
+            sub_level("a", &ROOM, vec![primary(3, 2)]),
+            sub_level("b", &ROOM, vec![]),
+        ];
+        let mut walled_in = puzzle(&meta_level(&placement), &sub_levels);
+        assert!(walled_in.apply_move(Direction::Right).is_none());
+
+        let meta_level = MetaLevel {
+            cross_boundaries: true,
+            ..meta_level(&placement)
+        };
+        let mut puzzle = puzzle(&meta_level, &sub_levels);
+        puzzle.apply_move(Direction::Up);
+        // there is no sub-level above this one
+        assert!(puzzle.apply_move(Direction::Up).is_none());
+        let results = puzzle
+            .apply_move(Direction::Right)
+            .expect("primary can cross");
+        assert_eq!(
+            results,
+            vec![MoveResult {
+                piece: Piece::Player(0),
+                motion: Motion::Step {
+                    direction: Direction::Right,
+                    distance: 1,
+                },
+                sub_level: puzzle.sub_level_index("b").unwrap(),
+                // just past the neighbor's boundary
+                coords: GridCoords::new(1, 3),
+            }]
+        );
+        assert_eq!(
+            puzzle.piece_sub_level(Piece::Player(0)),
+            puzzle.sub_level_index("b").unwrap()
+        );
+    }
+
+    #[test]
+    fn neighbors_move_front_to_back_and_never_share_a_tile() {
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b")]),
+            &[
+                sub_level("a", &ROOM, vec![primary(1, 1)]),
+                // a row that can move, and one that is up against the boundary
+                sub_level(
+                    "b",
+                    &ROOM,
+                    vec![player(1, 1), player(2, 1), player(2, 2), player(3, 2)],
+                ),
+            ],
+        );
+        let index = |iid| puzzle.player_index(iid).unwrap();
+        let (back, front) = (index("player 1,1"), index("player 2,1"));
+        let (stuck_back, stuck_front) = (index("player 2,2"), index("player 3,2"));
+        let results = puzzle
+            .apply_move(Direction::Right)
+            .expect("primary can move");
+        let moved = results
+            .iter()
+            .map(|result| result.piece)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            moved,
+            vec![
+                Piece::Player(puzzle.primary()),
+                Piece::Player(front),
+                Piece::Player(back)
+            ]
+        );
+        assert_eq!(puzzle.players()[front].coords, GridCoords::new(3, 3));
+        assert_eq!(puzzle.players()[back].coords, GridCoords::new(2, 3));
+        assert_eq!(puzzle.players()[stuck_front].coords, GridCoords::new(3, 2));
+        assert_eq!(puzzle.players()[stuck_back].coords, GridCoords::new(2, 2));
+    }
+
+    #[test]
+    fn players_slide_across_ice_until_something_stops_them() {
+        let room: [&[i32]; 4] = [
+            &[B, B, B, B, B, B, B],
+            &[B, F, I, I, I, W, B],
+            &[B, F, I, I, F, F, B],
+            &[B, B, B, B, B, B, B],
+        ];
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b")]),
+            &[
+                sub_level("a", &room, vec![primary(1, 1)]),
+                sub_level("b", &room, vec![player(1, 2)]),
+            ],
+        );
+        let results = puzzle
+            .apply_move(Direction::Right)
+            .expect("primary can move");
+        let slide = |player, sub_level, coords| MoveResult {
+            piece: Piece::Player(player),
+            motion: Motion::Step {
+                direction: Direction::Right,
+                distance: 3,
+            },
+            sub_level,
+            coords,
+        };
+        assert_eq!(
+            results,
+            vec![
+                // stops in front of the wall
+                slide(0, 0, GridCoords::new(4, 2)),
+                // stops on the first tile that isn't ice
+                slide(1, 1, GridCoords::new(4, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn conveyors_move_players_front_to_back_after_the_turn() {
+        let room: [&[i32]; 5] = [
+            &[B, B, B, B, B],
+            &[B, F, F, F, B],
+            &[B, C, C, F, B],
+            &[B, F, F, F, B],
+            &[B, B, B, B, B],
+        ];
+        // players in the primary player's own sub-level only move on the belt
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a")]),
+            &[sub_level(
+                "a",
+                &room,
+                vec![primary(1, 3), player(1, 2), player(2, 2)],
+            )],
+        );
+        let (back, front) = (
+            puzzle.player_index("player 1,2").unwrap(),
+            puzzle.player_index("player 2,2").unwrap(),
+        );
+        let results = puzzle
+            .apply_move(Direction::Right)
+            .expect("primary can move");
+        let moved = results
+            .iter()
+            .map(|result| (result.piece, result.coords))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            moved,
+            vec![
+                (Piece::Player(puzzle.primary()), GridCoords::new(2, 1)),
+                (Piece::Player(front), GridCoords::new(3, 2)),
+                (Piece::Player(back), GridCoords::new(2, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn one_way_tiles_are_only_entered_and_left_in_their_direction() {
+        let room: [&[i32]; 5] = [
+            &[B, B, B, B, B],
+            &[B, F, O, F, B],
+            &[B, F, F, F, B],
+            &[B, F, F, F, B],
+            &[B, B, B, B, B],
+        ];
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a")]),
+            &[sub_level("a", &room, vec![primary(2, 2)])],
+        );
+        // entering from the side
+        assert!(puzzle.apply_move(Direction::Up).is_none());
+        puzzle.apply_move(Direction::Left);
+        puzzle.apply_move(Direction::Up);
+        assert!(puzzle.apply_move(Direction::Right).is_some());
+        // leaving sideways or backwards
+        assert!(puzzle.apply_move(Direction::Down).is_none());
+        assert!(puzzle.apply_move(Direction::Left).is_none());
+        assert!(puzzle.apply_move(Direction::Right).is_some());
+        // entering backwards
+        assert!(puzzle.apply_move(Direction::Left).is_none());
+        assert_eq!(puzzle.players()[0].coords, GridCoords::new(3, 3));
+    }
+
+    #[test]
+    fn doors_are_open_only_while_a_player_stands_on_one_of_their_plates() {
+        let door = EntityKind::Door {
+            plates: vec!["plate".to_owned()],
+        };
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b")]),
+            &[
+                sub_level("a", &ROOM, vec![primary(1, 2), entity("door", 2, 2, door)]),
+                sub_level(
+                    "b",
+                    &ROOM,
+                    vec![player(1, 2), entity("plate", 1, 3, EntityKind::Plate)],
+                ),
+            ],
+        );
+        let door = puzzle.door_index("door").unwrap();
+        assert!(!puzzle.is_door_open(door));
+        assert!(puzzle.apply_move(Direction::Right).is_none());
+        // the neighbor steps onto the plate
+        puzzle.apply_move(Direction::Down);
+        assert!(puzzle.is_door_open(door));
+        puzzle.apply_move(Direction::Up);
+        assert!(!puzzle.is_door_open(door));
+    }
+
+    #[test]
+    fn cracked_floor_turns_deadly_once_a_player_steps_off_it() {
+        let room: [&[i32]; 5] = [
+            &[B, B, B, B, B],
+            &[B, F, K, F, B],
+            &[B, F, F, F, B],
+            &[B, F, F, F, B],
+            &[B, B, B, B, B],
+        ];
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a")]),
+            &[sub_level("a", &room, vec![primary(1, 1)])],
+        );
+        let cracked = GridCoords::new(2, 3);
+        puzzle.apply_move(Direction::Right);
+        assert!(!puzzle.is_crumbled(0, cracked));
+        assert!(!puzzle.is_lost());
+        puzzle.apply_move(Direction::Right);
+        assert!(puzzle.is_crumbled(0, cracked));
+        assert!(puzzle.is_deadly(0, cracked));
+        assert_eq!(puzzle.tile(0, cracked), tile_definitions().behavior(L));
+        puzzle.apply_move(Direction::Left);
+        assert!(puzzle.is_lost());
+    }
+
+    #[test]
+    fn teleporters_only_send_players_to_a_free_partner() {
+        let teleporter = |iid, x, y, partner: &str| {
+            let kind = EntityKind::Teleporter {
+                partner: Some(partner.to_owned()),
+            };
+            entity(iid, x, y, kind)
+        };
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b")]),
+            &[
+                sub_level(
+                    "a",
+                    &ROOM,
+                    vec![
+                        primary(1, 1),
+                        teleporter("a in", 2, 1, "a out"),
+                        teleporter("a out", 3, 3, "a in"),
+                        // blocks the way out
+                        boxed(3, 3),
+                    ],
+                ),
+                sub_level(
+                    "b",
+                    &ROOM,
+                    vec![
+                        player(1, 1),
+                        teleporter("b in", 2, 1, "b out"),
+                        teleporter("b out", 3, 3, "b in"),
+                    ],
+                ),
+            ],
+        );
+        let results = puzzle
+            .apply_move(Direction::Right)
+            .expect("primary can move");
+        let moved = results
+            .iter()
+            .map(|result| (result.piece, result.motion, result.coords))
+            .collect::<Vec<_>>();
+        let step = Motion::Step {
+            direction: Direction::Right,
+            distance: 1,
+        };
+        assert_eq!(
+            moved,
+            vec![
+                (Piece::Player(0), step, GridCoords::new(2, 3)),
+                (Piece::Player(1), step, GridCoords::new(2, 3)),
+                (Piece::Player(1), Motion::Teleport, GridCoords::new(3, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn players_only_teleport_at_the_end_of_a_turn_they_moved_in() {
+        let teleporter = |iid, x, y, partner: &str| {
+            let kind = EntityKind::Teleporter {
+                partner: Some(partner.to_owned()),
+            };
+            entity(iid, x, y, kind)
+        };
+        let ignoring = EntityKind::Player {
+            primary: false,
+            response: PlayerResponse::Ignore,
+        };
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b")]),
+            &[
+                sub_level("a", &ROOM, vec![primary(1, 1)]),
+                sub_level(
+                    "b",
+                    &ROOM,
+                    vec![
+                        // already standing on a teleporter, with a free partner
+                        entity("ignoring", 2, 1, ignoring),
+                        teleporter("b in", 2, 1, "b out"),
+                        teleporter("b out", 3, 3, "b in"),
+                    ],
+                ),
+            ],
+        );
+        let results = puzzle
+            .apply_move(Direction::Right)
+            .expect("primary can move");
+        assert_eq!(results.len(), 1);
+        assert_eq!(puzzle.players()[1].coords, GridCoords::new(2, 3));
+        assert!(puzzle.move_players_on_teleporters(&[0]).is_empty());
+        let teleported = puzzle.move_players_on_teleporters(&[1]);
+        assert_eq!(teleported.len(), 1);
+        assert_eq!(puzzle.players()[1].coords, GridCoords::new(3, 1));
+    }
+
+    #[test]
+    fn player_responses_map_the_direction_their_neighbor_moves() {
+        use Direction::*;
+        use PlayerResponse::*;
+        let cases = [
+            (Same, [Some(Up), Some(Down), Some(Left), Some(Right)]),
+            (Inverted, [Some(Down), Some(Up), Some(Right), Some(Left)]),
+            (MirrorX, [Some(Up), Some(Down), Some(Right), Some(Left)]),
+            (MirrorY, [Some(Down), Some(Up), Some(Left), Some(Right)]),
+            (Ignore, [None; 4]),
+        ];
+        for (response, expected) in cases {
+            let actual = [Up, Down, Left, Right].map(|direction| response.respond(direction));
+            assert_eq!(actual, expected, "{response:?}");
+        }
+    }
+
+    #[test]
+    fn neighbors_move_the_way_their_response_says() {
+        let responding = |response| {
+            let kind = EntityKind::Player {
+                primary: false,
+                response,
+            };
+            entity("responding", 2, 2, kind)
+        };
+        let mut puzzle = puzzle(
+            &meta_level(&[(0, 0, "a"), (0, 1, "b"), (1, 0, "c")]),
+            &[
+                sub_level("a", &ROOM, vec![primary(1, 1)]),
+                sub_level("b", &ROOM, vec![responding(PlayerResponse::Inverted)]),
+                sub_level("c", &ROOM, vec![responding(PlayerResponse::Ignore)]),
+            ],
+        );
+        puzzle
+            .apply_move(Direction::Down)
+            .expect("primary can move");
+        assert_eq!(puzzle.players()[0].coords, GridCoords::new(1, 2));
+        assert_eq!(puzzle.players()[1].coords, GridCoords::new(2, 3));
+        assert_eq!(puzzle.players()[2].coords, GridCoords::new(2, 2));
+    }
+
+    #[test]
+    fn influence_decides_which_neighbors_move_along() {
+        use Influence::*;
+        let placement = [(0, 0, "a"), (0, 1, "b"), (1, 0, "c"), (1, 1, "d")];
+        let sub_levels = [
+            sub_level("a", &ROOM, vec![primary(1, 1)]),
+            sub_level("b", &ROOM, vec![player(1, 1)]),
+            sub_level("c", &ROOM, vec![player(1, 1)]),
+            sub_level("d", &ROOM, vec![player(1, 1)]),
+        ];
+        // whether the players in b, c and d move along
+        let cases = [
+            (Orthogonal, [true, true, false]),
+            (OrthogonalAndDiagonal, [true, true, true]),
+            (SameRow, [true, false, false]),
+            (SameColumn, [false, true, false]),
+            (All, [true, true, true]),
+        ];
+        for (influence, expected) in cases {
+            let meta_level = MetaLevel {
+                influence,
+                ..meta_level(&placement)
+            };
+            let mut puzzle = puzzle(&meta_level, &sub_levels);
+            puzzle
+                .apply_move(Direction::Right)
+                .expect("primary can move");
+            let moved = [1, 2, 3].map(|player| puzzle.players()[player].coords.x == 2);
+            assert_eq!(moved, expected, "{influence:?}");
+        }
+    }
+
+    #[test]
+    fn sliding_only_swaps_with_an_empty_neighboring_slot() {
+        let placement = [(0, 0, "a"), (0, 1, "b"), (1, 0, "c")];
+        let sub_levels = [
+            sub_level("a", &ROOM, vec![primary(1, 1)]),
+            sub_level("b", &ROOM, vec![player(1, 1)]),
+            sub_level("c", &ROOM, vec![player(1, 1)]),
+        ];
+        let swapping = puzzle(&meta_level(&placement), &sub_levels);
+        let mut sliding = puzzle(
+            &MetaLevel {
+                sliding: true,
+                ..meta_level(&placement)
+            },
+            &sub_levels,
+        );
+        let pos = MetaGridCoords::new;
+        assert!(swapping.can_swap(pos(0, 0), pos(0, 1)));
+        assert!(!sliding.can_swap(pos(0, 0), pos(0, 1)));
+        // the empty slot is only diagonal from a
+        assert!(swapping.can_swap(pos(0, 0), pos(1, 1)));
+        assert!(!sliding.can_swap(pos(0, 0), pos(1, 1)));
+        assert!(sliding.can_swap(pos(1, 1), pos(0, 1)));
+        assert!(sliding.apply_swap(pos(0, 1), pos(1, 1)));
+        assert_eq!(sliding.position(1), pos(1, 1));
+        assert!(sliding.can_swap(pos(0, 0), pos(0, 1)));
+    }
+
+    #[test]
+    fn orientations_convert_back_and_forth() {
+        use Direction::*;
+        let orientations = [false, true].into_iter().flat_map(|flipped| {
+            (0..4).map(move |quarter_turns| Orientation {
+                flipped,
+                quarter_turns,
+            })
+        });
+        // not square, so that mixing up width and height shows
+        let (width, height) = (3, 2);
+        for orientation in orientations {
+            for direction in [Up, Down, Left, Right] {
+                let screen = orientation.to_screen(direction);
+                assert_eq!(orientation.to_local(screen), direction, "{orientation:?}");
+                assert_eq!(
+                    orientation.turned(Turn::Rotate).to_screen(direction),
+                    screen.rotated_clockwise(),
+                    "{orientation:?}"
+                );
+                assert_eq!(
+                    orientation.turned(Turn::Flip).to_screen(direction),
+                    screen.mirrored(),
+                    "{orientation:?}"
+                );
+            }
+            for (x, y) in (0..width).flat_map(|x| (0..height).map(move |y| (x, y))) {
+                let coords = GridCoords::new(x, y);
+                let slot = orientation.to_slot(coords, width, height);
+                assert_eq!(
+                    orientation.to_local_coords(slot, width, height),
+                    coords,
+                    "{orientation:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn primary_switches_are_unlimited_off_or_counted() {
+        let placement = [(0, 0, "a"), (0, 1, "b")];
+        let sub_levels = [
+            sub_level("a", &ROOM, vec![primary(1, 1)]),
+            sub_level("b", &ROOM, vec![player(1, 1)]),
+        ];
+        let switches = |primary_switches| {
+            let meta_level = MetaLevel {
+                primary_switches,
+                ..meta_level(&placement)
+            };
+            let mut puzzle = puzzle(&meta_level, &sub_levels);
+            // switch back and forth until it is no longer allowed
+            (0..5)
+                .take_while(|&switch| puzzle.apply_switch_primary(1 - switch % 2))
+                .count()
+        };
+        assert_eq!(switches(-1), 5);
+        assert_eq!(switches(0), 0);
+        assert_eq!(switches(2), 2);
+    }
+
+    #[test]
+    fn wraparound_makes_opposite_edges_neighbors() {
+        let pos = MetaGridCoords::new;
+        // one row of three columns
+        let wrap = Some((1, 3));
+        assert!(!pos(0, 0).is_neighbor(pos(0, 2), None));
+        assert!(pos(0, 0).is_neighbor(pos(0, 2), wrap));
+        assert!(pos(0, 0).is_neighbor(pos(0, 1), wrap));
+        // a single row wraps onto itself, which doesn't make it a neighbor
+        assert!(!pos(0, 0).is_neighbor(pos(0, 0), wrap));
+
+        let placement = [(0, 0, "a"), (0, 1, "b"), (0, 2, "c")];
+        let sub_levels = [
+            sub_level("a", &ROOM, vec![primary(1, 2)]),
+            sub_level("b", &ROOM, vec![]),
+            sub_level("c", &ROOM, vec![]),
+        ];
+        let crossing = MetaLevel {
+            cross_boundaries: true,
+            ..meta_level(&placement)
+        };
+        let mut at_the_edge = puzzle(&crossing, &sub_levels);
+        assert!(at_the_edge.apply_move(Direction::Left).is_none());
+
+        let mut puzzle = puzzle(
+            &MetaLevel {
+                wraparound: true,
+                ..crossing
+            },
+            &sub_levels,
+        );
+        puzzle
+            .apply_move(Direction::Left)
+            .expect("primary can cross the wrapped edge");
+        assert_eq!(
+            puzzle.players()[0],
+            PuzzlePlayer {
+                sub_level: puzzle.sub_level_index("c").unwrap(),
+                // just past the right boundary of the last column
+                coords: GridCoords::new(3, 2),
+            }
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::VecDeque;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::level::LoadLevelEvent;
+use crate::{
+    history::{RedoEvent, UndoEvent},
+    level::{
+        CurrentMetaLevel, CurrentPuzzle, LevelRespawnCountdown, LevelSpawnCountdown,
+        MetaGridCoords, ReloadLevelEvent,
+    },
+    player::{
+        Direction, IsMoving, PlayerIndex, PrimaryPlayer, SwitchPrimaryEvent, TryMovePlayerEvent,
+    },
+    puzzle::Turn,
+    ui::{SwapLevelsEvent, TurnLevelEvent},
+    GameState,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_arch = "wasm32"))]
+const REPLAY_PATH: &str = "replay.ron";
+#[cfg(not(target_arch = "wasm32"))]
+const REPLAY_STEP_SEC: f32 = 0.2;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayRecording>()
+            .add_system(clear_recording.in_schedule(OnEnter(GameState::InGame)))
+            .add_system(stop_playback.in_schedule(OnExit(GameState::InGame)))
+            .add_systems(
+                (
+                    record_actions,
+                    replay_input,
+                    play_replay.run_if(resource_exists::<ReplayPlayback>()),
+                )
+                    .in_set(OnUpdate(GameState::InGame)),
+            );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayAction {
+    Move(Direction),
+    Swap {
+        from: MetaGridCoords,
+        to: MetaGridCoords,
+    },
+    Turn {
+        pos: MetaGridCoords,
+        turn: Turn,
+    },
+    SwitchPrimary {
+        player: usize,
+    },
+    Reset,
+    Undo,
+    Redo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    pub level_num: i32,
+    pub action: ReplayAction,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Replay {
+    pub entries: Vec<ReplayEntry>,
+}
+
+// ===================
+// ==== RESOURCES ====
+// ===================
+
+#[derive(Resource, Default)]
+pub struct ReplayRecording {
+    replay: Replay,
+    // the level is briefly unloaded during a reset, so remember which one it was
+    level_num: i32,
+}
+
+impl ReplayRecording {
+    fn push(&mut self, action: ReplayAction) {
+        self.replay.entries.push(ReplayEntry {
+            level_num: self.level_num,
+            action,
+        });
+    }
+}
+
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    entries: VecDeque<ReplayEntry>,
+    timer: Timer,
+    // set whenever a level is (re)loaded, until its primary player has spawned
+    waiting_for_spawn: bool,
+}
+
+// =================
+// ==== SYSTEMS ====
+// =================
+
+fn clear_recording(mut recording: ResMut<ReplayRecording>) {
+    *recording = ReplayRecording::default();
+}
+
+fn stop_playback(mut commands: Commands) {
+    commands.remove_resource::<ReplayPlayback>();
+}
+
+fn record_actions(
+    mut recording: ResMut<ReplayRecording>,
+    current_level: Option<Res<CurrentMetaLevel>>,
+    mut move_events: EventReader<TryMovePlayerEvent>,
+    mut swap_events: EventReader<SwapLevelsEvent>,
+    mut turn_events: EventReader<TurnLevelEvent>,
+    mut switch_events: EventReader<SwitchPrimaryEvent>,
+    mut reload_events: EventReader<ReloadLevelEvent>,
+    mut undo_events: EventReader<UndoEvent>,
+    mut redo_events: EventReader<RedoEvent>,
+) {
+    if let Some(current_level) = current_level {
+        recording.level_num = current_level.0.level_num;
+    }
+    for event in move_events.iter() {
+        recording.push(ReplayAction::Move(event.direction));
+    }
+    for event in swap_events.iter() {
+        recording.push(ReplayAction::Swap {
+            from: event.from_pos,
+            to: event.to_pos,
+        });
+    }
+    for event in turn_events.iter() {
+        recording.push(ReplayAction::Turn {
+            pos: event.pos,
+            turn: event.turn,
+        });
+    }
+    for event in switch_events.iter() {
+        recording.push(ReplayAction::SwitchPrimary {
+            player: event.player,
+        });
+    }
+    // this includes the reload after stepping on lava, which playback skips
+    for _ in reload_events.iter() {
+        recording.push(ReplayAction::Reset);
+    }
+    for _ in undo_events.iter() {
+        recording.push(ReplayAction::Undo);
+    }
+    for _ in redo_events.iter() {
+        recording.push(ReplayAction::Redo);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn replay_input(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut recording: ResMut<ReplayRecording>,
+    mut load_events: EventWriter<LoadLevelEvent>,
+) {
+    if input.just_pressed(KeyCode::F5) {
+        match ron::ser::to_string_pretty(&recording.replay, default()) {
+            Ok(contents) => match std::fs::write(REPLAY_PATH, contents) {
+                Ok(()) => info!("saved replay to {REPLAY_PATH}"),
+                Err(err) => error!("could not write {REPLAY_PATH}: {err}"),
+            },
+            Err(err) => error!("could not serialize replay: {err}"),
+        }
+    }
+    if input.just_pressed(KeyCode::F6) {
+        let replay = match load_replay() {
+            Ok(replay) => replay,
+            Err(err) => {
+                error!("could not load {REPLAY_PATH}: {err}");
+                return;
+            }
+        };
+        let Some(first) = replay.entries.first() else {
+            warn!("{REPLAY_PATH} is empty");
+            return;
+        };
+        // the playback is recorded again, so the recording stays accurate
+        *recording = ReplayRecording::default();
+        load_events.send(LoadLevelEvent {
+            level_num: first.level_num,
+        });
+        commands.insert_resource(ReplayPlayback {
+            entries: replay.entries.into(),
+            timer: Timer::from_seconds(REPLAY_STEP_SEC, TimerMode::Once),
+            waiting_for_spawn: true,
+        });
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_replay() -> Result<Replay, String> {
+    let contents = std::fs::read_to_string(REPLAY_PATH).map_err(|err| err.to_string())?;
+    ron::from_str(&contents).map_err(|err| err.to_string())
+}
+
+// replay files can't be read or written on the web
+#[cfg(target_arch = "wasm32")]
+fn replay_input() {}
+
+fn play_replay(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut playback: ResMut<ReplayPlayback>,
+    current_level: Option<Res<CurrentMetaLevel>>,
+    current_puzzle: Option<Res<CurrentPuzzle>>,
+    level_spawn_countdown: Option<Res<LevelSpawnCountdown>>,
+    level_respawn_countdown: Option<Res<LevelRespawnCountdown>>,
+    spawned_primary_players: Query<(), (Added<PlayerIndex>, With<PrimaryPlayer>)>,
+    primary_players: Query<Entity, (With<PrimaryPlayer>, With<PlayerIndex>)>,
+    moving_players: Query<(), With<IsMoving>>,
+    mut move_events: EventWriter<TryMovePlayerEvent>,
+    mut swap_events: EventWriter<SwapLevelsEvent>,
+    mut turn_events: EventWriter<TurnLevelEvent>,
+    mut switch_events: EventWriter<SwitchPrimaryEvent>,
+    mut reload_events: EventWriter<ReloadLevelEvent>,
+    // grouped to stay within the number of parameters a system can take
+    (mut undo_events, mut redo_events): (EventWriter<UndoEvent>, EventWriter<RedoEvent>),
+) {
+    if current_puzzle
+        .as_ref()
+        .is_none_or(|puzzle| puzzle.is_added())
+    {
+        playback.waiting_for_spawn = true;
+    }
+    if !spawned_primary_players.is_empty() {
+        playback.waiting_for_spawn = false;
+    }
+    let Some(current_level) = current_level else {
+        return;
+    };
+    // wait for the level to load and for every player to finish moving
+    if playback.waiting_for_spawn || level_spawn_countdown.is_some() || !moving_players.is_empty() {
+        return;
+    }
+    let Some(&entry) = playback.entries.front() else {
+        info!("replay finished");
+        commands.remove_resource::<ReplayPlayback>();
+        return;
+    };
+    if entry.level_num != current_level.0.level_num {
+        warn!(
+            "replay expected level {} but level {} is loaded, stopping playback",
+            entry.level_num + 1,
+            current_level.0.level_num + 1
+        );
+        commands.remove_resource::<ReplayPlayback>();
+        return;
+    }
+    if level_respawn_countdown.is_some() {
+        match entry.action {
+            // the level is already about to reload by itself
+            ReplayAction::Reset => {
+                playback.entries.pop_front();
+                return;
+            }
+            ReplayAction::Undo => {}
+            _ => return,
+        }
+    }
+    if !playback.timer.tick(time.delta()).finished() {
+        return;
+    }
+    playback.timer.reset();
+    playback.entries.pop_front();
+
+    match entry.action {
+        ReplayAction::Move(direction) => move_events.send(TryMovePlayerEvent {
+            player: primary_players.single(),
+            direction,
+        }),
+        ReplayAction::Swap { from, to } => swap_events.send(SwapLevelsEvent {
+            from_pos: from,
+            to_pos: to,
+        }),
+        ReplayAction::Turn { pos, turn } => turn_events.send(TurnLevelEvent { pos, turn }),
+        ReplayAction::SwitchPrimary { player } => switch_events.send(SwitchPrimaryEvent { player }),
+        ReplayAction::Reset => reload_events.send(ReloadLevelEvent),
+        ReplayAction::Undo => undo_events.send(UndoEvent),
+        ReplayAction::Redo => redo_events.send(RedoEvent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_survive_a_round_trip_through_ron() {
+        let pos = MetaGridCoords::new;
+        let actions = [
+            ReplayAction::Move(Direction::Left),
+            ReplayAction::Swap {
+                from: pos(0, 0),
+                to: pos(1, 2),
+            },
+            ReplayAction::Turn {
+                pos: pos(1, 0),
+                turn: Turn::Flip,
+            },
+            ReplayAction::SwitchPrimary { player: 1 },
+            ReplayAction::Reset,
+            ReplayAction::Undo,
+            ReplayAction::Redo,
+        ];
+        let replay = Replay {
+            entries: actions
+                .into_iter()
+                .enumerate()
+                .map(|(level_num, action)| ReplayEntry {
+                    level_num: level_num as i32,
+                    action,
+                })
+                .collect(),
+        };
+        // the same way F5 writes it
+        let contents = ron::ser::to_string_pretty(&replay, default()).expect("replay serializes");
+        let loaded: Replay = ron::from_str(&contents).expect("replay deserializes");
+        assert_eq!(loaded, replay);
+    }
+}
@@ -0,0 +1,132 @@
+use std::{collections::VecDeque, fmt};
+
+use crate::{
+    level::MetaGridCoords,
+    player::Direction,
+    puzzle::{PuzzleState, Turn},
+};
+use bevy::utils::HashSet;
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Move(Direction),
+    Swap(MetaGridCoords, MetaGridCoords),
+    Turn(MetaGridCoords, Turn),
+    // by player index
+    SwitchPrimary(usize),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Move(direction) => write!(f, "{direction:?}"),
+            Self::Swap(from, to) => write!(
+                f,
+                "Swap({},{})<->({},{})",
+                from.row, from.col, to.row, to.col
+            ),
+            Self::Turn(pos, turn) => write!(f, "{turn:?}({},{})", pos.row, pos.col),
+            Self::SwitchPrimary(player) => write!(f, "SwitchPrimary({player})"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SolveOutcome {
+    Solved(Vec<Action>),
+    Unsolvable,
+    SearchLimitReached,
+}
+
+impl PuzzleState {
+    // Applies an action the same way the game does. Returns false if the
+    // action was rejected and the state is unchanged.
+    pub fn apply_action(&mut self, action: Action) -> bool {
+        match action {
+            Action::Move(direction) => self.apply_move(direction).is_some(),
+            Action::Swap(from_pos, to_pos) => self.apply_swap(from_pos, to_pos),
+            Action::Turn(pos, turn) => self.apply_turn(pos, turn),
+            Action::SwitchPrimary(player) => self.apply_switch_primary(player),
+        }
+    }
+
+    pub fn possible_actions(&self) -> Vec<Action> {
+        let positions = (0..self.meta_grid_height())
+            .flat_map(|row| {
+                (0..self.meta_grid_width()).map(move |col| MetaGridCoords::new(row, col))
+            })
+            // locked sub-levels can't be swapped or turned
+            .filter(|&pos| !self.is_locked(pos))
+            .collect::<Vec<_>>();
+        let moves = DIRECTIONS.into_iter().map(Action::Move);
+        let swaps = positions.iter().enumerate().flat_map(|(i, &from)| {
+            positions[i + 1..]
+                .iter()
+                .map(move |&to| Action::Swap(from, to))
+        });
+        // most meta-levels don't allow turning, so don't bother trying
+        let turns = positions
+            .iter()
+            .filter(|_| self.is_rotatable())
+            .flat_map(|&pos| [Turn::Rotate, Turn::Flip].map(|turn| Action::Turn(pos, turn)));
+        // none at all in meta-levels that don't allow switching. switching to
+        // the player that is already primary is rejected like any other action
+        // that doesn't apply
+        let switches = (0..self.players().len())
+            .filter(|_| self.switches_left() != Some(0))
+            .map(Action::SwitchPrimary);
+        moves.chain(swaps).chain(turns).chain(switches).collect()
+    }
+}
+
+// Breadth-first search over every kind of action. Every action costs one,
+// the same as `MoveCount`, so the first solution found is optimal.
+pub fn solve(start: &PuzzleState, max_states: usize) -> SolveOutcome {
+    if start.is_won() {
+        return SolveOutcome::Solved(vec![]);
+    }
+    let actions = start.possible_actions();
+    // every visited state is only kept here. the queue owns the states that
+    // still have to be expanded, so that only the frontier is stored twice
+    let mut visited = HashSet::default();
+    visited.insert(start.clone());
+    // the parent and action that led to each queued state, by queue order
+    let mut parents: Vec<Option<(usize, Action)>> = vec![None];
+    let mut queue = VecDeque::from([(0, start.clone())]);
+
+    while let Some((index, state)) = queue.pop_front() {
+        for &action in &actions {
+            let mut next = state.clone();
+            if !next.apply_action(action) || visited.contains(&next) {
+                continue;
+            }
+            if next.is_won() {
+                let mut path = vec![action];
+                let mut parent = index;
+                while let Some((grandparent, action)) = parents[parent] {
+                    path.push(action);
+                    parent = grandparent;
+                }
+                path.reverse();
+                return SolveOutcome::Solved(path);
+            }
+            if visited.len() >= max_states {
+                return SolveOutcome::SearchLimitReached;
+            }
+            visited.insert(next.clone());
+            // there's no coming back from lava
+            if !next.is_lost() {
+                queue.push_back((parents.len(), next));
+                parents.push(Some((index, action)));
+            }
+        }
+    }
+    SolveOutcome::Unsolvable
+}
@@ -0,0 +1,43 @@
+use crate::{GameState, GRID_SIZE};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+const TELEPORTER_COLOR: Color = Color::rgb(0.55, 0.3, 0.75);
+
+pub struct TeleporterPlugin;
+
+impl Plugin for TeleporterPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_ldtk_entity::<TeleporterBundle>("Teleporter")
+            .add_system(add_teleporter_sprites.in_set(OnUpdate(GameState::InGame)));
+    }
+}
+
+// ====================
+// ==== COMPONENTS ====
+// ====================
+
+// the puzzle reads the partner from the LDtk project, so this is only drawn
+#[derive(Component, Default)]
+pub struct Teleporter;
+
+#[derive(Bundle, LdtkEntity)]
+pub struct TeleporterBundle {
+    teleporter: Teleporter,
+    #[grid_coords]
+    grid_coords: GridCoords,
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+// =================
+// ==== SYSTEMS ====
+// =================
+
+// the tileset has no art for teleporters either
+fn add_teleporter_sprites(mut teleporters: Query<&mut Sprite, Added<Teleporter>>) {
+    for mut sprite in &mut teleporters {
+        sprite.color = TELEPORTER_COLOR;
+        sprite.custom_size = Some(Vec2::splat(GRID_SIZE as f32 * 0.75));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use crate::player::Direction;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+pub struct TilePlugin;
+
+impl Plugin for TilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<TileDefinitions>()
+            .init_asset_loader::<TileDefinitionsLoader>();
+    }
+}
+
+// How a tile affects whatever stands on it. Every flag defaults to off, so a
+// tile with none of them set is plain floor.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default)]
+pub struct TileBehavior {
+    // can't be walked onto
+    pub solid: bool,
+    // the solid edge of a sub-level, which players can cross if the
+    // meta-level allows it
+    pub boundary: bool,
+    // every goal needs a player or box on it to win
+    pub goal: bool,
+    // a player standing on it loses the level
+    pub deadly: bool,
+    // players keep sliding across it
+    pub slippery: bool,
+    // the value this turns into once a player steps off it
+    pub crumbles_into: Option<i32>,
+    // moves whoever stands on it one tile at the end of every turn
+    pub conveyor: Option<Direction>,
+    // can only be entered and left going in this direction
+    pub one_way: Option<Direction>,
+}
+
+// How a tile is drawn in the Tiles layer. LDtk's auto-layer rules pick a tile
+// first, and anything set here replaces it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TileLook {
+    // index into the level tileset
+    pub index: Option<u32>,
+    // multiplies the tile's own colors
+    pub tint: Option<(f32, f32, f32)>,
+}
+
+impl TileLook {
+    pub fn color(&self) -> Color {
+        self.tint.map_or(Color::WHITE, |(red, green, blue)| {
+            Color::rgb(red, green, blue)
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TileDefinition {
+    // only there to make the file readable, LDtk has its own identifiers
+    pub name: String,
+    #[serde(default)]
+    pub behavior: TileBehavior,
+    #[serde(default)]
+    pub look: TileLook,
+}
+
+// The behavior of every value in the TileData IntGrid layer.
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "8c5d3d6e-5a8f-4e0b-9a43-3f1d2b7c6e91"]
+pub struct TileDefinitions {
+    pub tiles: BTreeMap<i32, TileDefinition>,
+}
+
+impl TileDefinitions {
+    pub fn from_ron(contents: &str) -> Result<Self, ron::error::SpannedError> {
+        ron::from_str(contents)
+    }
+
+    pub fn behavior(&self, value: i32) -> Option<TileBehavior> {
+        self.tiles.get(&value).map(|definition| definition.behavior)
+    }
+
+    pub fn look(&self, value: i32) -> Option<TileLook> {
+        self.tiles.get(&value).map(|definition| definition.look)
+    }
+}
+
+#[derive(Default)]
+struct TileDefinitionsLoader;
+
+impl AssetLoader for TileDefinitionsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let definitions = ron::de::from_bytes::<TileDefinitions>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(definitions));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tiles.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shipped() -> TileDefinitions {
+        TileDefinitions::from_ron(include_str!("../assets/levels/beside_yourself.tiles.ron"))
+            .expect("tile definitions parse")
+    }
+
+    #[test]
+    fn shipped_definitions_map_values_to_behaviors() {
+        let definitions = shipped();
+        assert_eq!(definitions.behavior(1), Some(TileBehavior::default()));
+        let wall = definitions.behavior(3).expect("walls are defined");
+        assert!(wall.solid && !wall.boundary);
+        let boundary = definitions.behavior(4).expect("boundaries are defined");
+        assert!(boundary.solid && boundary.boundary);
+        assert!(definitions.behavior(5).is_some_and(|lava| lava.deadly));
+        assert_eq!(
+            definitions
+                .behavior(10)
+                .and_then(|conveyor| conveyor.conveyor),
+            Some(Direction::Right)
+        );
+        assert_eq!(
+            definitions
+                .behavior(15)
+                .and_then(|cracked| cracked.crumbles_into),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn unknown_values_have_no_behavior() {
+        let definitions = shipped();
+        assert_eq!(definitions.behavior(0), None);
+        assert_eq!(definitions.behavior(99), None);
+        assert_eq!(definitions.look(99), None);
+    }
+}
@@ -11,30 +11,68 @@ use bevy::{
 };
 
 use crate::{
-    level::{CurrentMetaLevel, LevelPosition, MetaGridCoords, MoveCount},
+    hint::{HintCount, ShownHint},
+    history::History,
+    level::{
+        CurrentMetaLevel, CurrentPuzzle, LevelCompleteEvent, LevelPosition, LevelRespawnCountdown,
+        MetaGridCoords, MoveCount, SubLevelIndex,
+    },
     loading::GameAssets,
+    player::{PlayerIndex, SwitchPrimaryEvent},
+    puzzle::{PuzzleState, Turn},
+    replay::ReplayPlayback,
+    solver::Action,
     GameState, MainCamera, DRAG_RENDER_LAYER, GRID_SIZE, MAIN_RENDER_LAYER, Z_OFFSET_UI,
 };
 
+const LOCKED_OVERLAY_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.2);
+const EMPTY_SLOT_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.08);
+
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<SwapLevelsEvent>()
+            .add_event::<TurnLevelEvent>()
             .add_systems(
                 (spawn_ui_root, setup_image_render_target).in_schedule(OnEnter(GameState::InGame)),
             )
             .add_systems(
                 (
                     swap_levels,
+                    turn_levels,
                     update_cursor_icon,
                     drag_icon,
                     highlight_drag_areas.run_if(resource_exists::<Dragging>()),
                     unhighlight_drag_areas.run_if(not(resource_exists::<Dragging>())),
                     sync_move_count.run_if(resource_exists_and_changed::<MoveCount>()),
+                    sync_hint_count.run_if(resource_exists_and_changed::<HintCount>()),
+                    show_level_complete_overlay,
                     spawn_rest_of_ui.run_if(resource_exists_and_changed::<CurrentMetaLevel>()),
-                    begin_drag.run_if(not(resource_exists::<Dragging>())),
+                    // the level is about to be reloaded or rewound after a
+                    // death, and only undo is allowed until then
+                    begin_drag.run_if(
+                        not(resource_exists::<Dragging>())
+                            .and_then(not(resource_exists::<ReplayPlayback>()))
+                            .and_then(not(resource_exists::<LevelRespawnCountdown>())),
+                    ),
                     end_drag.run_if(resource_exists::<Dragging>()),
+                    turn_on_right_click.run_if(
+                        not(resource_exists::<Dragging>())
+                            .and_then(not(resource_exists::<ReplayPlayback>()))
+                            .and_then(not(resource_exists::<LevelRespawnCountdown>())),
+                    ),
+                )
+                    .distributive_run_if(resource_exists::<CurrentMetaLevel>())
+                    .in_set(OnUpdate(GameState::InGame)),
+            )
+            .add_systems(
+                (
+                    click_to_switch_primary.run_if(
+                        not(resource_exists::<ReplayPlayback>())
+                            .and_then(not(resource_exists::<LevelRespawnCountdown>())),
+                    ),
+                    sync_switch_count.run_if(resource_exists_and_changed::<CurrentPuzzle>()),
                 )
                     .distributive_run_if(resource_exists::<CurrentMetaLevel>())
                     .in_set(OnUpdate(GameState::InGame)),
@@ -55,9 +93,14 @@ pub struct Dragging {
 // ==== EVENTS ====
 // ================
 
-struct SwapLevelsEvent {
-    from_pos: MetaGridCoords,
-    to_pos: MetaGridCoords,
+pub struct SwapLevelsEvent {
+    pub from_pos: MetaGridCoords,
+    pub to_pos: MetaGridCoords,
+}
+
+pub struct TurnLevelEvent {
+    pub pos: MetaGridCoords,
+    pub turn: Turn,
 }
 
 // ====================
@@ -82,6 +125,10 @@ pub struct DragArea;
 #[derive(Component)]
 pub struct DragAreaPosition(MetaGridCoords);
 
+// shown over sub-levels that can't be dragged
+#[derive(Component)]
+pub struct LockOverlay;
+
 #[derive(Component)]
 pub struct LevelUiRoot;
 
@@ -91,28 +138,68 @@ pub struct LevelTitle;
 #[derive(Component)]
 pub struct MoveCountText;
 
+#[derive(Component)]
+pub struct HintCountText;
+
+#[derive(Component)]
+pub struct SwitchCountText;
+
+#[derive(Component)]
+pub struct LevelCompleteOverlay;
+
 // =================
 // ==== SYSTEMS ====
 // =================
 
 fn swap_levels(
     current_level: Res<CurrentMetaLevel>,
+    mut current_puzzle: ResMut<CurrentPuzzle>,
     mut swap_events: EventReader<SwapLevelsEvent>,
-    mut ldtk_levels: Query<(&mut LevelPosition, &mut Transform)>,
+    mut ldtk_levels: Query<(&SubLevelIndex, &mut LevelPosition, &mut Transform)>,
     mut move_count: ResMut<MoveCount>,
+    mut history: ResMut<History>,
 ) {
     for event in swap_events.iter() {
-        if event.to_pos == event.from_pos {
+        let before = current_puzzle.0.clone();
+        if !current_puzzle.0.apply_swap(event.from_pos, event.to_pos) {
             continue;
         }
+        history.record(before, move_count.0);
         move_count.0 += 1;
-        for (mut level_pos, mut transform) in &mut ldtk_levels {
-            if level_pos.0 == event.from_pos {
-                *level_pos = LevelPosition(event.to_pos);
-                transform.translation = current_level.0.get_translation(event.to_pos).extend(0.);
-            } else if level_pos.0 == event.to_pos {
-                *level_pos = LevelPosition(event.from_pos);
-                transform.translation = current_level.0.get_translation(event.from_pos).extend(0.);
+        for (sub_level_index, mut level_pos, mut transform) in &mut ldtk_levels {
+            if level_pos.0 == event.from_pos || level_pos.0 == event.to_pos {
+                let grid_pos = current_puzzle.0.position(sub_level_index.0);
+                *level_pos = LevelPosition(grid_pos);
+                *transform = current_level
+                    .0
+                    .get_transform(grid_pos, current_puzzle.0.orientation(sub_level_index.0));
+            }
+        }
+    }
+}
+
+// the tiles and pieces of a sub-level are all children of its level entity,
+// so turning the level entity turns them too
+fn turn_levels(
+    current_level: Res<CurrentMetaLevel>,
+    mut current_puzzle: ResMut<CurrentPuzzle>,
+    mut turn_events: EventReader<TurnLevelEvent>,
+    mut ldtk_levels: Query<(&SubLevelIndex, &LevelPosition, &mut Transform)>,
+    mut move_count: ResMut<MoveCount>,
+    mut history: ResMut<History>,
+) {
+    for event in turn_events.iter() {
+        let before = current_puzzle.0.clone();
+        if !current_puzzle.0.apply_turn(event.pos, event.turn) {
+            continue;
+        }
+        history.record(before, move_count.0);
+        move_count.0 += 1;
+        for (sub_level_index, level_pos, mut transform) in &mut ldtk_levels {
+            if level_pos.0 == event.pos {
+                *transform = current_level
+                    .0
+                    .get_transform(event.pos, current_puzzle.0.orientation(sub_level_index.0));
             }
         }
     }
@@ -213,25 +300,59 @@ fn spawn_rest_of_ui(
             },
             ..default()
         });
-        parent.spawn(MoveCountText).insert(TextBundle {
-            text: Text::from_section(
-                "Moves: 0",
-                TextStyle {
-                    font: game_assets.main_font.clone(),
-                    font_size: 48.,
-                    color: Color::rgb(0.1, 0.1, 0.1),
-                },
-            ),
-            style: Style {
-                position_type: PositionType::Absolute,
-                position: UiRect {
-                    bottom: Val::Px(16.),
+        parent
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        bottom: Val::Px(16.),
+                        ..default()
+                    },
                     ..default()
                 },
                 ..default()
-            },
-            ..default()
-        });
+            })
+            .with_children(|parent| {
+                parent.spawn(MoveCountText).insert(TextBundle::from_section(
+                    "Moves: 0",
+                    TextStyle {
+                        font: game_assets.main_font.clone(),
+                        font_size: 48.,
+                        color: Color::rgb(0.1, 0.1, 0.1),
+                    },
+                ));
+                parent.spawn(HintCountText).insert(
+                    TextBundle::from_section(
+                        "Hints: 0",
+                        TextStyle {
+                            font: game_assets.main_font.clone(),
+                            font_size: 48.,
+                            color: Color::rgb(0.1, 0.1, 0.1),
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect::left(Val::Px(48.)),
+                        ..default()
+                    }),
+                );
+                // only shown when there is a limit to count down
+                if current_level.0.primary_switches > 0 {
+                    parent.spawn(SwitchCountText).insert(
+                        TextBundle::from_section(
+                            format!("Switches: {}", current_level.0.primary_switches),
+                            TextStyle {
+                                font: game_assets.main_font.clone(),
+                                font_size: 48.,
+                                color: Color::rgb(0.1, 0.1, 0.1),
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::left(Val::Px(48.)),
+                            ..default()
+                        }),
+                    );
+                }
+            });
     });
 
     let container = commands
@@ -251,8 +372,9 @@ fn spawn_rest_of_ui(
 
     for col in 0..current_level.0.meta_grid_width {
         for row in 0..current_level.0.meta_grid_height {
+            let pos = MetaGridCoords::new(row, col);
             let drag_area = commands
-                .spawn((DragArea, DragAreaPosition(MetaGridCoords::new(row, col))))
+                .spawn((DragArea, DragAreaPosition(pos)))
                 .insert(NodeBundle {
                     style: Style {
                         size: Size::new(
@@ -277,6 +399,33 @@ fn spawn_rest_of_ui(
                 .insert(RelativeCursorPosition::default())
                 .id();
             commands.entity(container).add_child(drag_area);
+            // the drag area's own background is used for highlighting, so the
+            // overlay is a child node
+            if current_level.0.is_locked(pos) {
+                commands.entity(drag_area).with_children(|parent| {
+                    parent
+                        .spawn(LockOverlay)
+                        .insert(NodeBundle {
+                            style: Style {
+                                size: Size::all(Val::Percent(100.)),
+                                padding: UiRect::all(Val::Px(4.)),
+                                ..default()
+                            },
+                            background_color: LOCKED_OVERLAY_COLOR.into(),
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                "Locked",
+                                TextStyle {
+                                    font: game_assets.main_font.clone(),
+                                    font_size: 24.,
+                                    color: Color::rgb(0.9, 0.9, 0.9),
+                                },
+                            ));
+                        });
+                });
+            }
         }
     }
 }
@@ -290,18 +439,96 @@ fn sync_move_count(
     }
 }
 
+fn sync_hint_count(
+    hint_count: Res<HintCount>,
+    mut hint_count_texts: Query<&mut Text, With<HintCountText>>,
+) {
+    for mut text in &mut hint_count_texts {
+        text.sections[0].value = format!("Hints: {}", hint_count.0);
+    }
+}
+
+fn sync_switch_count(
+    current_puzzle: Res<CurrentPuzzle>,
+    mut switch_count_texts: Query<&mut Text, With<SwitchCountText>>,
+) {
+    let Some(switches_left) = current_puzzle.0.switches_left() else {
+        return;
+    };
+    for mut text in &mut switch_count_texts {
+        text.sections[0].value = format!("Switches: {switches_left}");
+    }
+}
+
+// the overlay is a child of the UI root, so it's cleared along with the rest of
+// the level's UI once the next level spawns
+fn show_level_complete_overlay(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    mut complete_events: EventReader<LevelCompleteEvent>,
+    ui_root_query: Query<Entity, With<DragUiRoot>>,
+) {
+    let Some(event) = complete_events.iter().last() else {
+        return;
+    };
+    let rating = match (event.stars, event.par) {
+        (Some(stars), Some(par)) => format!(
+            "{} {} - {} moves, par {par}",
+            "*".repeat(stars as usize),
+            if stars == 1 { "star" } else { "stars" },
+            event.move_count
+        ),
+        _ => format!("{} moves", event.move_count),
+    };
+    let text_style = TextStyle {
+        font: game_assets.main_font.clone(),
+        font_size: 48.,
+        color: Color::rgb(0.1, 0.1, 0.1),
+    };
+    let overlay = commands
+        .spawn(LevelCompleteOverlay)
+        .insert(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                padding: UiRect::all(Val::Px(16.)),
+                ..default()
+            },
+            background_color: Color::rgba(1., 1., 1., 0.9).into(),
+            // drawn above the drag areas
+            z_index: ZIndex::Global(1),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Level complete!",
+                TextStyle {
+                    font_size: 72.,
+                    ..text_style.clone()
+                },
+            ));
+            parent.spawn(TextBundle::from_section(rating, text_style));
+        })
+        .id();
+    commands.entity(ui_root_query.single()).add_child(overlay);
+}
+
 fn update_cursor_icon(
+    current_level: Res<CurrentMetaLevel>,
+    current_puzzle: Res<CurrentPuzzle>,
     dragging: Option<Res<Dragging>>,
     mut windows: Query<&mut Window>,
-    drag_areas: Query<&RelativeCursorPosition, With<DragArea>>,
+    drag_areas: Query<(&RelativeCursorPosition, &DragAreaPosition), With<DragArea>>,
 ) {
     let mut window = windows.single_mut();
     window.cursor.icon = if dragging.is_some() {
         CursorIcon::Grabbing
-    } else if drag_areas
-        .iter()
-        .any(|rel_cursor_pos| rel_cursor_pos.mouse_over())
-    {
+    } else if drag_areas.iter().any(|(rel_cursor_pos, drag_area_pos)| {
+        rel_cursor_pos.mouse_over()
+            && !current_level.0.is_locked(drag_area_pos.0)
+            && current_puzzle.0.sub_level_at(drag_area_pos.0).is_some()
+    }) {
         CursorIcon::Grab
     } else {
         CursorIcon::Default
@@ -311,13 +538,19 @@ fn update_cursor_icon(
 fn begin_drag(
     mut commands: Commands,
     current_level: Res<CurrentMetaLevel>,
+    current_puzzle: Res<CurrentPuzzle>,
     input: Res<Input<MouseButton>>,
     drag_areas: Query<(&RelativeCursorPosition, &DragAreaPosition)>,
     mut drag_sprite: Query<&mut Sprite, With<DragSprite>>,
 ) {
     if input.just_pressed(MouseButton::Left) {
         for (rel_cursor_pos, drag_area_pos) in drag_areas.iter() {
-            if rel_cursor_pos.mouse_over() {
+            // there's nothing to pick up in an empty slot, but levels can
+            // still be dropped into one
+            if rel_cursor_pos.mouse_over()
+                && !current_level.0.is_locked(drag_area_pos.0)
+                && current_puzzle.0.sub_level_at(drag_area_pos.0).is_some()
+            {
                 commands.insert_resource(Dragging {
                     from_pos: drag_area_pos.0,
                 });
@@ -337,6 +570,75 @@ fn begin_drag(
     }
 }
 
+// right-click rotates a sub-level, and shift right-click mirrors it
+fn turn_on_right_click(
+    mouse_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    drag_areas: Query<(&RelativeCursorPosition, &DragAreaPosition)>,
+    mut turn_events: EventWriter<TurnLevelEvent>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let turn = if keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        Turn::Flip
+    } else {
+        Turn::Rotate
+    };
+    for (rel_cursor_pos, drag_area_pos) in drag_areas.iter() {
+        if rel_cursor_pos.mouse_over() {
+            turn_events.send(TurnLevelEvent {
+                pos: drag_area_pos.0,
+                turn,
+            });
+        }
+    }
+}
+
+// Clicking on a player makes it the primary player. Pressing the mouse over a
+// sub-level starts dragging it, so a click is a drag that ends where it began.
+fn click_to_switch_primary(
+    current_puzzle: Res<CurrentPuzzle>,
+    input: Res<Input<MouseButton>>,
+    dragging: Option<Res<Dragging>>,
+    windows: Query<&Window>,
+    cameras: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    players: Query<(&PlayerIndex, &GlobalTransform)>,
+    mut switch_events: EventWriter<SwitchPrimaryEvent>,
+) {
+    if !input.just_released(MouseButton::Left) {
+        return;
+    }
+    let window = windows.single();
+    let (camera, camera_transform) = cameras.single();
+    let Some(mouse_world_pos) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
+        .map(|ray| ray.origin.truncate())
+    else {
+        return;
+    };
+    for (player_index, player_transform) in &players {
+        let Some(player) = current_puzzle.0.players().get(player_index.0) else {
+            continue;
+        };
+        let player_pos = current_puzzle.0.position(player.sub_level);
+        if dragging
+            .as_ref()
+            .is_some_and(|dragging| dragging.from_pos != player_pos)
+        {
+            continue;
+        }
+        // player sprites are centered on their tile
+        let offset = player_transform.translation().truncate() - mouse_world_pos;
+        if offset.abs().max_element() < GRID_SIZE as f32 / 2. {
+            switch_events.send(SwitchPrimaryEvent {
+                player: player_index.0,
+            });
+        }
+    }
+}
+
 fn drag_icon(
     dragging: Option<Res<Dragging>>,
     windows: Query<&Window>,
@@ -368,8 +670,10 @@ fn drag_icon(
 
 fn end_drag(
     mut commands: Commands,
+    current_level: Res<CurrentMetaLevel>,
     input: Res<Input<MouseButton>>,
     dragging: Res<Dragging>,
+    respawn_countdown: Option<Res<LevelRespawnCountdown>>,
     drag_areas: Query<(&RelativeCursorPosition, &DragAreaPosition)>,
     mut drag_sprite: Query<&mut Visibility, With<DragSprite>>,
     mut swap_events: EventWriter<SwapLevelsEvent>,
@@ -377,8 +681,12 @@ fn end_drag(
     if input.just_released(MouseButton::Left) {
         let mut sprite_visibility = drag_sprite.single_mut();
         *sprite_visibility = Visibility::Hidden;
+        // a drag that was picked up before a death is dropped without a swap
         for (rel_cursor_pos, drag_area_pos) in drag_areas.iter() {
-            if rel_cursor_pos.mouse_over() {
+            if rel_cursor_pos.mouse_over()
+                && !current_level.0.is_locked(drag_area_pos.0)
+                && respawn_countdown.is_none()
+            {
                 swap_events.send(SwapLevelsEvent {
                     from_pos: dragging.from_pos,
                     to_pos: drag_area_pos.0,
@@ -389,20 +697,41 @@ fn end_drag(
     }
 }
 
+// empty slots are tinted so they can be seen at all
+fn idle_drag_area_color(puzzle: &PuzzleState, pos: MetaGridCoords) -> BackgroundColor {
+    if puzzle.sub_level_at(pos).is_none() {
+        EMPTY_SLOT_COLOR.into()
+    } else {
+        Color::rgba(1., 1., 1., 0.).into()
+    }
+}
+
 fn unhighlight_drag_areas(
+    current_puzzle: Res<CurrentPuzzle>,
+    shown_hint: Option<Res<ShownHint>>,
     mut drag_areas: Query<(
         &RelativeCursorPosition,
         &DragAreaPosition,
         &mut BackgroundColor,
     )>,
 ) {
-    for (_, _, mut bg_color) in &mut drag_areas {
-        *bg_color = Color::rgba(1., 1., 1., 0.).into();
+    let hinted_positions = match shown_hint.map(|hint| hint.action) {
+        Some(Action::Swap(from_pos, to_pos)) => vec![from_pos, to_pos],
+        Some(Action::Turn(pos, _)) => vec![pos],
+        _ => vec![],
+    };
+    for (_, drag_pos, mut bg_color) in &mut drag_areas {
+        if hinted_positions.contains(&drag_pos.0) {
+            *bg_color = Color::rgba(0.9, 0.7, 0.1, 0.5).into();
+        } else {
+            *bg_color = idle_drag_area_color(&current_puzzle.0, drag_pos.0);
+        }
     }
 }
 
 
 fn highlight_drag_areas(
+    current_puzzle: Res<CurrentPuzzle>,
     dragging: Res<Dragging>,
     mut drag_areas: Query<(
         &RelativeCursorPosition,
@@ -411,10 +740,10 @@ fn highlight_drag_areas(
     )>,
 ) {
     for (rel_cursor_pos, drag_pos, mut bg_color) in &mut drag_areas {
-        if rel_cursor_pos.mouse_over() && drag_pos.0 != dragging.from_pos {
+        if rel_cursor_pos.mouse_over() && current_puzzle.0.can_swap(dragging.from_pos, drag_pos.0) {
             *bg_color = Color::rgba(0.1, 0.9, 0.1, 0.5).into();
         } else {
-            *bg_color = Color::rgba(1., 1., 1., 0.).into();
+            *bg_color = idle_drag_area_color(&current_puzzle.0, drag_pos.0);
         }
     }
 }
@@ -0,0 +1,615 @@
+use std::fmt;
+
+use crate::{
+    level::{bool_field, build_meta_level, int_field, MetaGridCoords},
+    loading::GameAssets,
+    puzzle::PuzzleState,
+    solver::{solve, SolveOutcome},
+    tiles::TileDefinitions,
+    GameState,
+};
+use bevy::{
+    prelude::*,
+    tasks::AsyncComputeTaskPool,
+    utils::{HashMap, HashSet},
+};
+use bevy_ecs_ldtk::{ldtk, prelude::*};
+
+// the startup check runs while the game is being played, so keep it cheap
+const STARTUP_MAX_STATES: usize = 200_000;
+
+// Checks the shipped level set when the game starts. Only added with the `dev`
+// feature.
+pub struct ValidationPlugin;
+
+impl Plugin for ValidationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(validate_on_load.in_schedule(OnExit(GameState::Loading)));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LevelIssue {
+    MissingField {
+        level: String,
+        field: &'static str,
+    },
+    LevelNumGap {
+        level_num: i32,
+    },
+    DuplicateGridPosition {
+        level_num: i32,
+        pos: MetaGridCoords,
+        levels: [String; 2],
+    },
+    MismatchedSize {
+        level_num: i32,
+        level: String,
+        size_px: IVec2,
+        expected_px: IVec2,
+    },
+    OverlappingEntities {
+        level: String,
+        grid: IVec2,
+    },
+    MissingPlayer {
+        level: String,
+    },
+    MissingGoal {
+        level: String,
+    },
+    UnknownTileValue {
+        level: String,
+        value: i32,
+    },
+    UnlinkedDoor {
+        level: String,
+        grid: IVec2,
+    },
+    UnpairedTeleporter {
+        level: String,
+        grid: IVec2,
+    },
+    PrimaryPlayerCount {
+        level_num: i32,
+        count: usize,
+    },
+    Unsolvable {
+        level_num: i32,
+    },
+}
+
+impl fmt::Display for LevelIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingField { level, field } => {
+                write!(f, "{level} has no {field} field")
+            }
+            Self::LevelNumGap { level_num } => write!(
+                f,
+                "no level has LevelNum {level_num}, so every later level is skipped"
+            ),
+            Self::DuplicateGridPosition {
+                level_num,
+                pos,
+                levels: [first, second],
+            } => write!(
+                f,
+                "{first} and {second} both use GridRow {} and GridCol {} in LevelNum {level_num}",
+                pos.row, pos.col
+            ),
+            Self::MismatchedSize {
+                level_num,
+                level,
+                size_px,
+                expected_px,
+            } => write!(
+                f,
+                "{level} is {}x{}px, but LevelNum {level_num} starts with a {}x{}px level",
+                size_px.x, size_px.y, expected_px.x, expected_px.y
+            ),
+            Self::OverlappingEntities { level, grid } => write!(
+                f,
+                "{level} has more than one player or box at {},{}",
+                grid.x, grid.y
+            ),
+            Self::MissingPlayer { level } => write!(f, "{level} has a goal but no Player"),
+            Self::MissingGoal { level } => write!(f, "{level} has a Player but no goal"),
+            Self::UnknownTileValue { level, value } => write!(
+                f,
+                "{level} uses IntGrid value {value}, which has no tile definition"
+            ),
+            Self::UnlinkedDoor { level, grid } => write!(
+                f,
+                "the door at {},{} in {level} isn't linked to a plate in the same LevelNum",
+                grid.x, grid.y
+            ),
+            Self::UnpairedTeleporter { level, grid } => write!(
+                f,
+                "the teleporter at {},{} in {level} has no partner in the same level",
+                grid.x, grid.y
+            ),
+            Self::PrimaryPlayerCount { level_num, count } => write!(
+                f,
+                "LevelNum {level_num} has {count} primary players instead of one"
+            ),
+            Self::Unsolvable { level_num } => write!(f, "LevelNum {level_num} cannot be solved"),
+        }
+    }
+}
+
+// Looks for the mistakes that otherwise only show up as panics or silently
+// missing content. Sub-levels with neither a player nor a goal are treated as
+// filler. Only meta-levels without structural issues are passed to the
+// solver, which gives up after `max_states` states without reporting anything.
+pub fn validate_levels<'a>(
+    levels: impl IntoIterator<Item = &'a ldtk::Level>,
+    tile_definitions: &TileDefinitions,
+    max_states: usize,
+) -> Vec<LevelIssue> {
+    let levels = levels.into_iter().collect::<Vec<_>>();
+    let mut issues = vec![];
+
+    let mut by_level_num: HashMap<i32, Vec<(MetaGridCoords, &ldtk::Level)>> = HashMap::new();
+    for level in &levels {
+        let fields =
+            ["LevelNum", "GridRow", "GridCol"].map(|field| (field, int_field(level, field)));
+        if let Some(&(field, _)) = fields.iter().find(|(_, value)| value.is_none()) {
+            issues.push(LevelIssue::MissingField {
+                level: level.identifier.clone(),
+                field,
+            });
+            continue;
+        }
+        let [level_num, row, col] = fields.map(|(_, value)| value.unwrap());
+        by_level_num
+            .entry(level_num)
+            .or_default()
+            .push((MetaGridCoords::new(row, col), level));
+    }
+
+    let max_level_num = by_level_num.keys().copied().max().unwrap_or(-1);
+    for level_num in 0..=max_level_num {
+        let Some(sub_levels) = by_level_num.get(&level_num) else {
+            issues.push(LevelIssue::LevelNumGap { level_num });
+            continue;
+        };
+        let issue_count = issues.len();
+
+        let mut seen: HashMap<MetaGridCoords, &str> = HashMap::new();
+        let (_, first) = sub_levels[0];
+        let expected_px = IVec2::new(first.px_wid, first.px_hei);
+        // players can walk to a goal in another sub-level, so don't pair them up
+        let cross_boundaries = sub_levels
+            .iter()
+            .any(|(_, level)| bool_field(level, "CrossBoundaries"));
+        // doors can be opened by plates in any sub-level of the meta-level
+        let plate_iids = sub_levels
+            .iter()
+            .flat_map(|(_, level)| level.layer_instances.iter().flatten())
+            .flat_map(|layer| &layer.entity_instances)
+            .filter(|instance| instance.identifier == "Plate")
+            .map(|instance| instance.iid.as_str())
+            .collect::<HashSet<_>>();
+        let mut primary_count = 0;
+        for &(pos, level) in sub_levels {
+            if let Some(other) = seen.insert(pos, &level.identifier) {
+                issues.push(LevelIssue::DuplicateGridPosition {
+                    level_num,
+                    pos,
+                    levels: [other.to_owned(), level.identifier.clone()],
+                });
+            }
+            let size_px = IVec2::new(level.px_wid, level.px_hei);
+            if size_px != expected_px {
+                issues.push(LevelIssue::MismatchedSize {
+                    level_num,
+                    level: level.identifier.clone(),
+                    size_px,
+                    expected_px,
+                });
+            }
+
+            let layers = level.layer_instances.iter().flatten();
+            let players = layers
+                .clone()
+                .flat_map(|layer| &layer.entity_instances)
+                .filter(|instance| instance.identifier == "Player")
+                .collect::<Vec<_>>();
+            // players and boxes block each other, so they can't start on the same tile
+            let mut occupied = HashSet::new();
+            for instance in layers
+                .clone()
+                .flat_map(|layer| &layer.entity_instances)
+                .filter(|instance| ["Player", "Box"].contains(&instance.identifier.as_str()))
+            {
+                if !occupied.insert(instance.grid) {
+                    issues.push(LevelIssue::OverlappingEntities {
+                        level: level.identifier.clone(),
+                        grid: instance.grid,
+                    });
+                }
+            }
+            for door in layers
+                .clone()
+                .flat_map(|layer| &layer.entity_instances)
+                .filter(|instance| instance.identifier == "Door")
+            {
+                let linked = door.field_instances.iter().any(|field| match &field.value {
+                    FieldValue::EntityRefs(refs) if field.identifier == "Plates" => refs
+                        .iter()
+                        .flatten()
+                        .any(|entity_ref| plate_iids.contains(entity_ref.entity_iid.as_str())),
+                    _ => false,
+                });
+                if !linked {
+                    issues.push(LevelIssue::UnlinkedDoor {
+                        level: level.identifier.clone(),
+                        grid: door.grid,
+                    });
+                }
+            }
+            let teleporters = layers
+                .clone()
+                .flat_map(|layer| &layer.entity_instances)
+                .filter(|instance| instance.identifier == "Teleporter")
+                .collect::<Vec<_>>();
+            for teleporter in &teleporters {
+                let paired = teleporter
+                    .field_instances
+                    .iter()
+                    .any(|field| match &field.value {
+                        FieldValue::EntityRef(Some(entity_ref))
+                            if field.identifier == "Partner" =>
+                        {
+                            teleporters.iter().any(|other| {
+                                other.iid == entity_ref.entity_iid && other.iid != teleporter.iid
+                            })
+                        }
+                        _ => false,
+                    });
+                if !paired {
+                    issues.push(LevelIssue::UnpairedTeleporter {
+                        level: level.identifier.clone(),
+                        grid: teleporter.grid,
+                    });
+                }
+            }
+            let mut tile_values = layers
+                .filter(|layer| layer.identifier == "TileData")
+                .flat_map(|layer| layer.int_grid_csv.iter().copied())
+                .filter(|&value| value != 0)
+                .collect::<Vec<_>>();
+            tile_values.sort();
+            tile_values.dedup();
+            for &value in &tile_values {
+                if tile_definitions.behavior(value).is_none() {
+                    issues.push(LevelIssue::UnknownTileValue {
+                        level: level.identifier.clone(),
+                        value,
+                    });
+                }
+            }
+            let has_goal = tile_values.iter().any(|&value| {
+                tile_definitions
+                    .behavior(value)
+                    .is_some_and(|behavior| behavior.goal)
+            });
+            primary_count += players
+                .iter()
+                .filter(|instance| {
+                    instance.field_instances.iter().any(|field| {
+                        field.identifier == "Primary"
+                            && matches!(field.value, FieldValue::Bool(true))
+                    })
+                })
+                .count();
+            if cross_boundaries {
+                continue;
+            }
+            if has_goal && players.is_empty() {
+                issues.push(LevelIssue::MissingPlayer {
+                    level: level.identifier.clone(),
+                });
+            } else if !has_goal && !players.is_empty() {
+                issues.push(LevelIssue::MissingGoal {
+                    level: level.identifier.clone(),
+                });
+            }
+        }
+        if primary_count != 1 {
+            issues.push(LevelIssue::PrimaryPlayerCount {
+                level_num,
+                count: primary_count,
+            });
+        }
+
+        if issues.len() > issue_count {
+            continue;
+        }
+        let meta_level =
+            build_meta_level(level_num, levels.iter().copied()).expect("LevelNum has sub-levels");
+        let puzzle =
+            PuzzleState::from_ldtk_levels(&meta_level, levels.iter().copied(), tile_definitions);
+        if let SolveOutcome::Unsolvable = solve(&puzzle, max_states) {
+            issues.push(LevelIssue::Unsolvable { level_num });
+        }
+    }
+
+    issues
+}
+
+// =================
+// ==== SYSTEMS ====
+// =================
+
+fn validate_on_load(
+    game_assets: Res<GameAssets>,
+    ldtk_assets: Res<Assets<LdtkAsset>>,
+    tile_definitions: Res<Assets<TileDefinitions>>,
+) {
+    let ldtk_asset = ldtk_assets
+        .get(&game_assets.levels)
+        .expect("LDtk asset exists");
+    let levels = ldtk_asset.iter_levels().cloned().collect::<Vec<_>>();
+    let tile_definitions = tile_definitions
+        .get(&game_assets.tiles)
+        .expect("tile definitions exist")
+        .clone();
+    // solving takes a while, so don't hold up the main menu
+    AsyncComputeTaskPool::get()
+        .spawn(async move {
+            for issue in validate_levels(&levels, &tile_definitions, STARTUP_MAX_STATES) {
+                warn!("level data: {issue}");
+            }
+        })
+        .detach();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::GRID_SIZE;
+    use bevy_ecs_ldtk::ldtk::LdtkJson;
+
+    const LDTK_PATH: &str = "assets/levels/beside_yourself.ldtk";
+    const TILES_PATH: &str = "assets/levels/beside_yourself.tiles.ron";
+
+    fn shipped_levels() -> Vec<ldtk::Level> {
+        let contents = fs::read_to_string(LDTK_PATH).expect("LDtk project can be read");
+        let project: LdtkJson = serde_json::from_str(&contents).expect("LDtk project parses");
+        project
+            .levels
+            .into_iter()
+            .chain(project.worlds.into_iter().flat_map(|world| world.levels))
+            .collect()
+    }
+
+    fn issues(levels: &[ldtk::Level]) -> Vec<LevelIssue> {
+        let contents = fs::read_to_string(TILES_PATH).expect("tile definitions can be read");
+        let tile_definitions =
+            TileDefinitions::from_ron(&contents).expect("tile definitions parse");
+        validate_levels(levels, &tile_definitions, STARTUP_MAX_STATES)
+    }
+
+    // the first meta-level is a single sub-level with one player and one goal,
+    // which the cases below break one way or another
+    fn first_level() -> ldtk::Level {
+        shipped_levels()
+            .into_iter()
+            .find(|level| int_field(level, "LevelNum") == Some(0))
+            .expect("LevelNum 0 exists")
+    }
+
+    fn set_int_field(level: &mut ldtk::Level, identifier: &str, value: i32) {
+        for field in &mut level.field_instances {
+            if field.identifier == identifier {
+                field.value = FieldValue::Int(Some(value));
+            }
+        }
+    }
+
+    fn layer<'a>(level: &'a mut ldtk::Level, identifier: &str) -> &'a mut ldtk::LayerInstance {
+        level
+            .layer_instances
+            .iter_mut()
+            .flatten()
+            .find(|layer| layer.identifier == identifier)
+            .expect("layer exists")
+    }
+
+    // a copy of the player with no fields, as some other kind of entity
+    fn add_entity(level: &mut ldtk::Level, identifier: &str) -> IVec2 {
+        let entities = &mut layer(level, "Entities").entity_instances;
+        let mut entity = entities[0].clone();
+        entity.identifier = identifier.to_owned();
+        entity.iid = format!("{identifier}-iid");
+        entity.field_instances.clear();
+        entity.grid += IVec2::new(1, 0);
+        let grid = entity.grid;
+        entities.push(entity);
+        grid
+    }
+
+    fn replace_tiles(level: &mut ldtk::Level, from: i32, to: i32) {
+        for value in &mut layer(level, "TileData").int_grid_csv {
+            if *value == from {
+                *value = to;
+            }
+        }
+    }
+
+    #[test]
+    fn shipped_levels_have_no_issues() {
+        assert_eq!(issues(&shipped_levels()), vec![]);
+    }
+
+    #[test]
+    fn missing_field() {
+        let mut level = first_level();
+        level
+            .field_instances
+            .retain(|field| field.identifier != "GridRow");
+        assert_eq!(
+            issues(&[level.clone()]),
+            vec![LevelIssue::MissingField {
+                level: level.identifier,
+                field: "GridRow",
+            }]
+        );
+    }
+
+    #[test]
+    fn level_num_gap() {
+        let mut level = first_level();
+        set_int_field(&mut level, "LevelNum", 1);
+        assert_eq!(
+            issues(&[level]),
+            vec![LevelIssue::LevelNumGap { level_num: 0 }]
+        );
+    }
+
+    #[test]
+    fn duplicate_grid_position() {
+        let level = first_level();
+        let mut copy = level.clone();
+        copy.identifier = "Copy".to_owned();
+        copy.iid = "copy-iid".to_owned();
+        let issues = issues(&[level.clone(), copy]);
+        assert!(issues.contains(&LevelIssue::DuplicateGridPosition {
+            level_num: 0,
+            pos: MetaGridCoords::new(0, 0),
+            levels: [level.identifier, "Copy".to_owned()],
+        }));
+    }
+
+    #[test]
+    fn mismatched_size() {
+        let level = first_level();
+        let mut copy = level.clone();
+        copy.identifier = "Copy".to_owned();
+        copy.iid = "copy-iid".to_owned();
+        set_int_field(&mut copy, "GridCol", 1);
+        copy.px_wid += GRID_SIZE;
+        let issues = issues(&[level.clone(), copy.clone()]);
+        assert!(issues.contains(&LevelIssue::MismatchedSize {
+            level_num: 0,
+            level: "Copy".to_owned(),
+            size_px: IVec2::new(copy.px_wid, copy.px_hei),
+            expected_px: IVec2::new(level.px_wid, level.px_hei),
+        }));
+    }
+
+    #[test]
+    fn overlapping_entities() {
+        let mut level = first_level();
+        add_entity(&mut level, "Box");
+        let entities = &mut layer(&mut level, "Entities").entity_instances;
+        let grid = entities[0].grid;
+        entities[1].grid = grid;
+        assert_eq!(
+            issues(&[level.clone()]),
+            vec![LevelIssue::OverlappingEntities {
+                level: level.identifier,
+                grid,
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_player() {
+        let mut level = first_level();
+        layer(&mut level, "Entities").entity_instances.clear();
+        assert_eq!(
+            issues(&[level.clone()]),
+            vec![
+                LevelIssue::MissingPlayer {
+                    level: level.identifier,
+                },
+                LevelIssue::PrimaryPlayerCount {
+                    level_num: 0,
+                    count: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_goal() {
+        let mut level = first_level();
+        replace_tiles(&mut level, 2, 1);
+        assert_eq!(
+            issues(&[level.clone()]),
+            vec![LevelIssue::MissingGoal {
+                level: level.identifier,
+            }]
+        );
+    }
+
+    #[test]
+    fn unknown_tile_value() {
+        let mut level = first_level();
+        replace_tiles(&mut level, 4, 99);
+        assert_eq!(
+            issues(&[level.clone()]),
+            vec![LevelIssue::UnknownTileValue {
+                level: level.identifier,
+                value: 99,
+            }]
+        );
+    }
+
+    #[test]
+    fn unlinked_door() {
+        let mut level = first_level();
+        let grid = add_entity(&mut level, "Door");
+        assert_eq!(
+            issues(&[level.clone()]),
+            vec![LevelIssue::UnlinkedDoor {
+                level: level.identifier,
+                grid,
+            }]
+        );
+    }
+
+    #[test]
+    fn unpaired_teleporter() {
+        let mut level = first_level();
+        let grid = add_entity(&mut level, "Teleporter");
+        assert_eq!(
+            issues(&[level.clone()]),
+            vec![LevelIssue::UnpairedTeleporter {
+                level: level.identifier,
+                grid,
+            }]
+        );
+    }
+
+    #[test]
+    fn primary_player_count() {
+        let mut level = first_level();
+        for field in &mut layer(&mut level, "Entities").entity_instances[0].field_instances {
+            field.value = FieldValue::Bool(false);
+        }
+        assert_eq!(
+            issues(&[level]),
+            vec![LevelIssue::PrimaryPlayerCount {
+                level_num: 0,
+                count: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn unsolvable() {
+        let mut level = first_level();
+        // walls the player in
+        replace_tiles(&mut level, 1, 3);
+        assert_eq!(
+            issues(&[level]),
+            vec![LevelIssue::Unsolvable { level_num: 0 }]
+        );
+    }
+}